@@ -0,0 +1,537 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Sub};
+
+// Arbitrary-precision integer behind int and uint. Anything that fits in an
+// i64 stays inline as Small; larger values are a sign plus a magnitude of
+// base 2^32 limbs, least significant first. The representation is canonical
+// (Large never holds a value that fits in Small, and has no high zero limbs),
+// so the derived equality is value equality.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BigInt {
+    Small(i64),
+    Large { negative: bool, limbs: Vec<u32> },
+}
+
+impl BigInt {
+    // parses an optionally signed run of decimal digits
+    pub fn parse(digits: &str) -> Option<BigInt> {
+        if let Ok(i) = digits.parse::<i64>() {
+            return Some(BigInt::Small(i));
+        }
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut limbs = Vec::new();
+        for b in digits.bytes() {
+            mul_small_add(&mut limbs, 10, (b - b'0') as u32);
+        }
+        Some(BigInt::from_parts(negative, limbs))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == BigInt::Small(0)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            BigInt::Small(i) => *i < 0,
+            BigInt::Large { negative, .. } => *negative,
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            BigInt::Small(i) => Some(*i),
+            BigInt::Large { .. } => None,
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self {
+            BigInt::Small(i) => u64::try_from(*i).ok(),
+            BigInt::Large { negative: false, limbs } if limbs.len() <= 2 => Some(low_u64(limbs)),
+            BigInt::Large { .. } => None,
+        }
+    }
+
+    // low 64 bits in two's complement, i.e. the value modulo 2^64
+    pub fn wrapping_u64(&self) -> u64 {
+        match self {
+            BigInt::Small(i) => *i as u64,
+            BigInt::Large { negative, limbs } => {
+                let low = low_u64(limbs);
+                if *negative { low.wrapping_neg() } else { low }
+            }
+        }
+    }
+
+    pub fn wrapping_i64(&self) -> i64 {
+        self.wrapping_u64() as i64
+    }
+
+    // quotient rounded toward zero and the remainder with the sign of self,
+    // matching Rust's integer / and %; None when dividing by zero
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        if let (BigInt::Small(a), BigInt::Small(b)) = (self, other) {
+            if let (Some(q), Some(r)) = (a.checked_div(*b), a.checked_rem(*b)) {
+                return Some((BigInt::Small(q), BigInt::Small(r)));
+            }
+        }
+        let (a_negative, a) = self.parts();
+        let (b_negative, b) = other.parts();
+        let (q, r) = divmod_mag(&a, &b);
+        Some((BigInt::from_parts(a_negative != b_negative, q), BigInt::from_parts(a_negative, r)))
+    }
+
+    fn from_parts(negative: bool, mut limbs: Vec<u32>) -> BigInt {
+        trim(&mut limbs);
+        if limbs.len() <= 2 {
+            let magnitude = low_u64(&limbs);
+            if !negative && magnitude <= i64::MAX as u64 {
+                return BigInt::Small(magnitude as i64);
+            }
+            if negative && magnitude <= 1 << 63 {
+                return BigInt::Small((magnitude as i64).wrapping_neg());
+            }
+        }
+        BigInt::Large { negative, limbs }
+    }
+
+    fn parts(&self) -> (bool, Vec<u32>) {
+        match self {
+            BigInt::Small(i) => (*i < 0, limbs_of(i.unsigned_abs())),
+            BigInt::Large { negative, limbs } => (*negative, limbs.clone()),
+        }
+    }
+
+    fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let (a_negative, a) = self.parts();
+        let (b_negative, b) = other.parts();
+        // one spare limb so the sign bit survives
+        let len = a.len().max(b.len()) + 1;
+        let a = to_twos(a_negative, a, len);
+        let b = to_twos(b_negative, b, len);
+        let limbs: Vec<u32> = a.iter().zip(&b).map(|(&x, &y)| op(x, y)).collect();
+        from_twos(limbs)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(i: i64) -> BigInt {
+        BigInt::Small(i)
+    }
+}
+
+impl From<u64> for BigInt {
+    fn from(u: u64) -> BigInt {
+        BigInt::from_parts(false, limbs_of(u))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        if let (BigInt::Small(a), BigInt::Small(b)) = (self, other) {
+            return a.cmp(b);
+        }
+        let (a_negative, a) = self.parts();
+        let (b_negative, b) = other.parts();
+        match (a_negative, b_negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&a, &b),
+            (true, true) => cmp_mag(&b, &a),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if let (BigInt::Small(a), BigInt::Small(b)) = (self, other) {
+            if let Some(sum) = a.checked_add(*b) {
+                return BigInt::Small(sum);
+            }
+        }
+        let (a_negative, a) = self.parts();
+        let (b_negative, b) = other.parts();
+        if a_negative == b_negative {
+            return BigInt::from_parts(a_negative, add_mag(&a, &b));
+        }
+        match cmp_mag(&a, &b) {
+            Ordering::Less => BigInt::from_parts(b_negative, sub_mag(&b, &a)),
+            _ => BigInt::from_parts(a_negative, sub_mag(&a, &b)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        if let (BigInt::Small(a), BigInt::Small(b)) = (self, other) {
+            if let Some(difference) = a.checked_sub(*b) {
+                return BigInt::Small(difference);
+            }
+        }
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        if let (BigInt::Small(a), BigInt::Small(b)) = (self, other) {
+            if let Some(product) = a.checked_mul(*b) {
+                return BigInt::Small(product);
+            }
+        }
+        let (a_negative, a) = self.parts();
+        let (b_negative, b) = other.parts();
+        BigInt::from_parts(a_negative != b_negative, mul_mag(&a, &b))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        if let BigInt::Small(i) = self {
+            if let Some(negated) = i.checked_neg() {
+                return BigInt::Small(negated);
+            }
+        }
+        let (negative, limbs) = self.parts();
+        BigInt::from_parts(!negative, limbs)
+    }
+}
+
+impl BitAnd for &BigInt {
+    type Output = BigInt;
+
+    fn bitand(self, other: &BigInt) -> BigInt {
+        match (self, other) {
+            (BigInt::Small(a), BigInt::Small(b)) => BigInt::Small(a & b),
+            _ => self.bitwise(other, |x, y| x & y),
+        }
+    }
+}
+
+impl BitOr for &BigInt {
+    type Output = BigInt;
+
+    fn bitor(self, other: &BigInt) -> BigInt {
+        match (self, other) {
+            (BigInt::Small(a), BigInt::Small(b)) => BigInt::Small(a | b),
+            _ => self.bitwise(other, |x, y| x | y),
+        }
+    }
+}
+
+impl BitXor for &BigInt {
+    type Output = BigInt;
+
+    fn bitxor(self, other: &BigInt) -> BigInt {
+        match (self, other) {
+            (BigInt::Small(a), BigInt::Small(b)) => BigInt::Small(a ^ b),
+            _ => self.bitwise(other, |x, y| x ^ y),
+        }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (negative, mut limbs) = match self {
+            BigInt::Small(i) => return write!(f, "{}", i),
+            BigInt::Large { negative, limbs } => (*negative, limbs.clone()),
+        };
+        // peel off nine decimal digits at a time, least significant first
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let (quotient, remainder) = divmod_small(&limbs, 1_000_000_000);
+            chunks.push(remainder);
+            limbs = quotient;
+        }
+        if negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn limbs_of(value: u64) -> Vec<u32> {
+    let mut limbs = vec![value as u32, (value >> 32) as u32];
+    trim(&mut limbs);
+    limbs
+}
+
+fn low_u64(limbs: &[u32]) -> u64 {
+    let low = limbs.first().copied().unwrap_or(0) as u64;
+    let high = limbs.get(1).copied().unwrap_or(0) as u64;
+    high << 32 | low
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+// magnitudes below are trimmed little-endian limb slices
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let sum = a.get(i).copied().unwrap_or(0) as u64 + b.get(i).copied().unwrap_or(0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry != 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// a - b, requires a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut difference = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+        result.push(difference as u32);
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_small_add(limbs: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    if carry != 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+fn divmod_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (i, &limb) in a.iter().enumerate().rev() {
+        let current = remainder << 32 | limb as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+// schoolbook binary long division, b must be non-zero
+fn divmod_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let (quotient, remainder) = divmod_small(a, *divisor);
+        return (quotient, limbs_of(remainder as u64));
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for bit in (0..a.len() * 32).rev() {
+        shl1(&mut remainder);
+        if a[bit / 32] >> (bit % 32) & 1 == 1 {
+            match remainder.first_mut() {
+                Some(low) => *low |= 1,
+                None => remainder.push(1),
+            }
+        }
+        if cmp_mag(&remainder, b) != Ordering::Less {
+            remainder = sub_mag(&remainder, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+fn shl1(limbs: &mut Vec<u32>) {
+    let mut carry = 0;
+    for limb in limbs.iter_mut() {
+        let next = *limb >> 31;
+        *limb = *limb << 1 | carry;
+        carry = next;
+    }
+    if carry != 0 {
+        limbs.push(carry);
+    }
+}
+
+fn to_twos(negative: bool, mut limbs: Vec<u32>, len: usize) -> Vec<u32> {
+    limbs.resize(len, 0);
+    if negative {
+        negate_twos(&mut limbs);
+    }
+    limbs
+}
+
+fn from_twos(mut limbs: Vec<u32>) -> BigInt {
+    let negative = limbs.last().is_some_and(|&top| top >> 31 == 1);
+    if negative {
+        negate_twos(&mut limbs);
+    }
+    BigInt::from_parts(negative, limbs)
+}
+
+fn negate_twos(limbs: &mut [u32]) {
+    let mut carry = true;
+    for limb in limbs.iter_mut() {
+        let (value, overflowed) = (!*limb).overflowing_add(carry as u32);
+        *limb = value;
+        carry = overflowed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(i: i128) -> BigInt {
+        BigInt::parse(&i.to_string()).unwrap()
+    }
+
+    fn small(b: &BigInt) -> i128 {
+        b.to_string().parse().unwrap()
+    }
+
+    // edge cases around the limb and i64 boundaries, plus a spread of
+    // pseudo-random values up to about 2^100
+    fn samples() -> Vec<i128> {
+        let mut values = vec![0, 1, -1, 2, -2, 7, -7, 1 << 31, 1 << 32, (1 << 32) - 1, 1 << 33];
+        values.extend([i64::MAX as i128, i64::MIN as i128, i64::MAX as i128 + 1, i64::MIN as i128 - 1]);
+        values.extend([u64::MAX as i128, u64::MAX as i128 + 1, 1 << 96, (1 << 100) - 1]);
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..40 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            values.push((state as i128) << (state % 37));
+        }
+        let negated: Vec<i128> = values.iter().map(|v| -v).collect();
+        values.extend(negated);
+        values
+    }
+
+    #[test]
+    fn arithmetic_matches_i128() {
+        for &a in &samples() {
+            for &b in &samples() {
+                let (x, y) = (big(a), big(b));
+                assert_eq!(small(&(&x + &y)), a + b, "{} + {}", a, b);
+                assert_eq!(small(&(&x - &y)), a - b, "{} - {}", a, b);
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!(small(&(&x * &y)), product, "{} * {}", a, b);
+                }
+                assert_eq!(x.cmp(&y), a.cmp(&b), "{} cmp {}", a, b);
+            }
+            assert_eq!(small(&-&big(a)), -a);
+        }
+    }
+
+    #[test]
+    fn long_division_matches_i128() {
+        for &a in &samples() {
+            for &b in &samples() {
+                let Some((q, r)) = big(a).div_rem(&big(b)) else {
+                    assert_eq!(b, 0);
+                    continue;
+                };
+                assert_eq!((small(&q), small(&r)), (a / b, a % b), "{} / {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn bitwise_ops_are_twos_complement() {
+        for &a in &samples() {
+            for &b in &samples() {
+                let (x, y) = (big(a), big(b));
+                assert_eq!(small(&(&x & &y)), a & b, "{} & {}", a, b);
+                assert_eq!(small(&(&x | &y)), a | b, "{} | {}", a, b);
+                assert_eq!(small(&(&x ^ &y)), a ^ b, "{} ^ {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn values_that_fit_are_small() {
+        assert_eq!(BigInt::from_parts(true, vec![0, 1 << 31]), BigInt::Small(i64::MIN));
+        assert_eq!(BigInt::from_parts(false, vec![u32::MAX, i32::MAX as u32]), BigInt::Small(i64::MAX));
+        assert_eq!(BigInt::from_parts(false, vec![0, 1 << 31]), BigInt::Large { negative: false, limbs: vec![0, 1 << 31] });
+        assert_eq!(BigInt::from_parts(true, vec![5, 0, 0]), BigInt::Small(-5));
+        assert_eq!(BigInt::from_parts(true, Vec::new()), BigInt::Small(0));
+        assert_eq!(&big(i64::MIN as i128 - 1) + &BigInt::Small(1), BigInt::Small(i64::MIN));
+        assert_eq!(-&BigInt::Small(i64::MIN), big(1 << 63));
+        for &a in &samples() {
+            assert_eq!(big(a).to_i64(), i64::try_from(a).ok(), "{}", a);
+        }
+    }
+
+    #[test]
+    fn display_pads_inner_chunks() {
+        for digits in ["1000000000", "1000000000000000000000", "-1000000000000000000000000000001", "123456789012345678901234567890123456789"] {
+            assert_eq!(BigInt::parse(digits).unwrap().to_string(), digits);
+        }
+        for &a in &samples() {
+            assert_eq!(big(a).to_string(), a.to_string());
+        }
+    }
+}
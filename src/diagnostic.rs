@@ -0,0 +1,198 @@
+use std::fmt::{self, Write};
+
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// codes are stable; new ones get appended, old ones are never renumbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnexpectedToken,
+    BadToken,
+    BadExpression,
+    UnexpectedEOF,
+    UndefinedIdentifier,
+    TypeMismatch,
+    InvalidCondition,
+    ReturnTypeMismatch,
+    UnknownMethod,
+    InvalidAssignTarget,
+    InvalidPassTo,
+    EmptyOption,
+    InvalidParam,
+    UndefinedVariable,
+    DivisionByZero,
+    InvalidOperand,
+    Unsupported,
+    ArithmeticOverflow,
+    NotCallable,
+    CannotInfer,
+    UnknownField,
+    ReturnOutsideFunction,
+    MutatedCapture,
+    NegativeUnsigned,
+    InvalidConversion,
+    UnhandledMember,
+    InvalidMutation,
+    CallPanicked,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedToken => "E0001",
+            ErrorCode::BadToken => "E0002",
+            ErrorCode::BadExpression => "E0003",
+            ErrorCode::UnexpectedEOF => "E0004",
+            ErrorCode::UndefinedIdentifier => "E0100",
+            ErrorCode::TypeMismatch => "E0101",
+            ErrorCode::InvalidCondition => "E0102",
+            ErrorCode::ReturnTypeMismatch => "E0103",
+            ErrorCode::UnknownMethod => "E0104",
+            ErrorCode::InvalidAssignTarget => "E0105",
+            ErrorCode::InvalidPassTo => "E0106",
+            ErrorCode::EmptyOption => "E0107",
+            ErrorCode::InvalidParam => "E0108",
+            ErrorCode::UndefinedVariable => "E0200",
+            ErrorCode::DivisionByZero => "E0201",
+            ErrorCode::InvalidOperand => "E0202",
+            ErrorCode::Unsupported => "E0203",
+            ErrorCode::ArithmeticOverflow => "E0204",
+            ErrorCode::NotCallable => "E0109",
+            ErrorCode::CannotInfer => "E0110",
+            ErrorCode::UnknownField => "E0111",
+            ErrorCode::ReturnOutsideFunction => "E0112",
+            ErrorCode::MutatedCapture => "E0113",
+            ErrorCode::NegativeUnsigned => "E0114",
+            ErrorCode::InvalidConversion => "E0115",
+            ErrorCode::UnhandledMember => "E0116",
+            ErrorCode::InvalidMutation => "E0117",
+            ErrorCode::CallPanicked => "E0205",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: impl Into<String>) -> Label {
+        Label { span, message: message.into() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: ErrorCode, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            primary: Label::new(span, ""),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(code: ErrorCode, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, span, message)
+    }
+
+    pub fn warning(code: ErrorCode, span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, span, message)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_primary_label(mut self, message: impl Into<String>) -> Diagnostic {
+        self.primary.message = message.into();
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{}[{}]: {}", self.severity, self.code.as_str(), self.message);
+
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        let line_text = |line: usize| -> &str {
+            let start = line_starts.get(line - 1).copied().unwrap_or(source.len());
+            let end = line_starts.get(line).map(|e| e - 1).unwrap_or(source.len());
+            source[start..end].trim_end_matches('\r')
+        };
+
+        let mut labels: Vec<(&Label, char)> = vec![(&self.primary, '^')];
+        labels.extend(self.secondary.iter().map(|l| (l, '-')));
+        let mut lines: Vec<usize> = labels.iter().map(|(l, _)| l.span.line.max(1) as usize).collect();
+        lines.sort();
+        lines.dedup();
+        let width = lines.last().map(|l| l.to_string().len()).unwrap_or(1);
+        let pad = " ".repeat(width);
+
+        let primary = self.primary.span;
+        let _ = writeln!(out, "{}--> {}:{}:{}", pad, file_name, primary.line, primary.col);
+        let _ = writeln!(out, "{} |", pad);
+        for line in lines {
+            let text = line_text(line);
+            let _ = writeln!(out, "{:>width$} | {}", line, text, width = width);
+            for (label, marker) in labels.iter().filter(|(l, _)| l.span.line.max(1) as usize == line) {
+                let col = label.span.col.max(1) as usize;
+                let line_start = line_starts.get(line - 1).copied().unwrap_or(source.len());
+                let line_end = line_start + text.len();
+                let start = (label.span.start as usize).min(line_end);
+                let end = (label.span.end as usize).clamp(start, line_end);
+                let len = source.get(start..end).map(|s| s.chars().count()).unwrap_or(0).max(1);
+                let _ = write!(out, "{} | {}{}", pad, " ".repeat(col - 1), marker.to_string().repeat(len));
+                if label.message.is_empty() {
+                    let _ = writeln!(out);
+                } else {
+                    let _ = writeln!(out, " {}", label.message);
+                }
+            }
+        }
+        if !self.notes.is_empty() {
+            let _ = writeln!(out, "{} |", pad);
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = note: {}", pad, note);
+        }
+        out
+    }
+}
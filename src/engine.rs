@@ -1,161 +1,1050 @@
 use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::{fmt, mem};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
 
-use crate::type_def::Type;
-use crate::expr::Expr;
-use crate::parser::Parser;
-use crate::lexer::{Lexer, Operator};
+use crate::bignum::BigInt;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::span::Span;
+use crate::type_def::{field_order, Type};
+use crate::expr::{Expr, ExprKind};
+use crate::lexer::Operator;
+use crate::scheduler::{Pending, ThreadPool};
 
 #[derive(Debug)]
 pub struct Interpreter {
-    variables: HashMap<String, Value>,
-    type_sig_map: HashMap<u64, Arc<Type>>,
-    type_name_map: HashMap<String, u64>,
+    // lexical scopes of the function being run, innermost last
+    scopes: Vec<HashMap<String, Value>>,
+    params: Vec<Value>,
+    // runs pure calls in the background; None runs everything in order on
+    // the current thread
+    pool: Option<Arc<ThreadPool>>,
+    // every call sent to the pool, so errors from ones never read still
+    // get reported
+    spawned: Vec<Arc<PendingValue>>,
+    // shared with the interpreters running this one's calls on the pool
+    copies: Arc<CopyCounter>,
 }
 
-#[derive(Clone, Debug)]
+type PendingValue = Pending<Result<Value, Diagnostic>>;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     None,
     Bool(bool),
-    Int(i64),
-    Uint(u64),
+    Int(BigInt),
+    Uint(BigInt),
     Char(char),
     Float(f64),
-    String(String),
+    // strings, arrays and structs share their payload between copies of a
+    // value until one of them is written to, see make_mut
+    String(Arc<String>),
     Type(Type),
+    Array(Arc<Vec<Value>>),
+    Struct(Arc<HashMap<String, Value>>),
+    Optional(Option<Box<Value>>),
+    // value of a ghost type, tagged so `.is(..)` can tell it apart from a
+    // value of the underlying type
+    Ghost {
+        name: Arc<String>,
+        value: Box<Value>,
+    },
+    // location behind variables declared with as_shared; only ever stored
+    // in scopes, reading a variable gives the value inside
+    Shared(SharedValue),
+    // result of a call still running on the pool; only ever stored in
+    // scopes, reading the variable waits for it
+    Pending(Arc<PendingValue>),
+    Function {
+        param_sig: Arc<Type>,
+        return_sig: Arc<Type>,
+        block: Arc<Expr>,
+        // snapshot of the bindings visible where the function was created;
+        // non-shared values are copies, so later writes on either side stay
+        // local to their own scope
+        captures: Arc<HashMap<String, Value>>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct SharedValue(Arc<RwLock<Value>>);
+
+impl SharedValue {
+    fn new(value: Value) -> SharedValue {
+        SharedValue(Arc::new(RwLock::new(value)))
+    }
+
+    fn get(&self) -> Value {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Value) -> T) -> T {
+        f(&mut self.0.write().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl PartialEq for SharedValue {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.get() == other.get()
+    }
+}
+
+// ways evaluation can leave an expression early; a Return travels up
+// through blocks and ifs until the enclosing call catches it
+#[derive(Debug)]
+enum Unwind {
+    Return(Value),
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Unwind {
+    fn from(diag: Diagnostic) -> Unwind {
+        Unwind::Error(diag)
+    }
+}
+
+impl Value {
+    // whether this value is of type t; values don't carry their type, so
+    // this goes by shape, and an empty array fits any array type
+    fn has_type(&self, t: &Type) -> bool {
+        match (self, t.resolve()) {
+            (Value::Ghost { name, .. }, Type::Ghost { name: t_name, .. }) => name == t_name,
+            (Value::Bool(_), Type::Bool)
+            | (Value::Int(_), Type::Int)
+            | (Value::Uint(_), Type::Uint)
+            | (Value::Char(_), Type::Char)
+            | (Value::Float(_), Type::Float)
+            | (Value::String(_), Type::String)
+            | (Value::Optional(None), Type::None) => true,
+            (Value::Array(values), Type::Array { array_type }) => values.iter().all(|v| v.has_type(array_type)),
+            (Value::Struct(fields), Type::Struct { pairs }) => {
+                fields.len() == pairs.len()
+                    && fields.iter().all(|(name, v)| t.field(name).is_some_and(|field| v.has_type(field)))
+            }
+            (Value::Optional(Some(value)), Type::Optional { members }) => members.iter().any(|m| value.has_type(m)),
+            (Value::Optional(None), Type::Optional { members }) => members.contains(&Type::None.into()),
+            (Value::Function { param_sig, return_sig, .. }, Type::Function { param_type, return_type }) => {
+                param_sig == param_type && return_sig == return_type
+            }
+            _ => false,
+        }
+    }
+
+    // whether two values are of the same type, as far as can be told from
+    // the values themselves
+    fn same_shape(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Ghost { name: a, value: a_value }, Value::Ghost { name: b, value: b_value }) => {
+                a == b && a_value.same_shape(b_value)
+            }
+            (Value::Array(a), Value::Array(b)) => match (a.first(), b.first()) {
+                (Some(a), Some(b)) => a.same_shape(b),
+                _ => true,
+            },
+            (Value::Struct(a), Value::Struct(b)) => {
+                a.len() == b.len() && a.iter().all(|(name, v)| b.get(name).is_some_and(|w| v.same_shape(w)))
+            }
+            (Value::Optional(Some(a)), Value::Optional(Some(b))) => a.same_shape(b),
+            (Value::Function { param_sig: a_param, return_sig: a_return, .. }, Value::Function { param_sig: b_param, return_sig: b_return, .. }) => {
+                a_param == b_param && a_return == b_return
+            }
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+
+    // type of this value as far as binary operators care; they only work
+    // on primitives and, for `+`, arrays of any element type
+    fn operand_type(&self) -> Option<Type> {
+        match self {
+            Value::Bool(_) => Some(Type::Bool),
+            Value::Int(_) => Some(Type::Int),
+            Value::Uint(_) => Some(Type::Uint),
+            Value::Char(_) => Some(Type::Char),
+            Value::Float(_) => Some(Type::Float),
+            Value::String(_) => Some(Type::String),
+            Value::Array(_) => Some(Type::Array { array_type: Type::Unknown.into() }),
+            _ => None,
+        }
+    }
+
+    // whether nothing reachable from this value can be written to by
+    // someone else; calls on pure values can run on another thread
+    fn is_pure(&self) -> bool {
+        match self {
+            Value::Shared(_) => false,
+            Value::Array(values) => values.iter().all(Value::is_pure),
+            Value::Struct(fields) => fields.values().all(Value::is_pure),
+            Value::Optional(Some(value)) | Value::Ghost { value, .. } => value.is_pure(),
+            Value::Function { captures, .. } => captures.values().all(Value::is_pure),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Uint(u) => write!(f, "{}u", u),
+            Value::Char(c) => write!(f, "'{}'", c),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::Type(t) => write!(f, "{}", t),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Struct(pairs) => {
+                let mut fields: Vec<_> = pairs.iter().collect();
+                fields.sort_by(|(a, _), (b, _)| field_order(a).cmp(&field_order(b)));
+                let is_tuple = !fields.is_empty() && fields.iter().enumerate().all(|(i, (name, _))| **name == i.to_string());
+                if is_tuple {
+                    write!(f, "(")?;
+                    for (i, (_, value)) in fields.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", value)?;
+                    }
+                    if fields.len() == 1 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, ")")
+                } else {
+                    write!(f, "{{")?;
+                    for (i, (name, value)) in fields.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, " {}: {}", name, value)?;
+                    }
+                    write!(f, " }}")
+                }
+            }
+            Value::Optional(Some(value)) => write!(f, "Some({})", value),
+            Value::Optional(None) => write!(f, "None"),
+            Value::Ghost { value, .. } => write!(f, "{}", value),
+            Value::Shared(shared) => write!(f, "{}", shared.get()),
+            Value::Pending(pending) => match pending.wait() {
+                Ok(value) => write!(f, "{}", value),
+                Err(_) => write!(f, "<error>"),
+            },
+            Value::Function { param_sig, return_sig, .. } => write!(f, "{} {{ .. }}", Type::Function { param_type: param_sig.clone(), return_type: return_sig.clone() }),
+        }
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Interpreter {
-            variables: HashMap::new(),
-            type_sig_map: HashMap::new(),
-            type_name_map: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            params: Vec::new(),
+            pool: None,
+            spawned: Vec::new(),
+            copies: Arc::default(),
         }
     }
 
-    pub fn interpret(&mut self, parser: &mut Parser) -> Result<(), String> {
-        let expr = parser.parse().map_err(|e| format!("{:?}", e))?;
-        self.evaluate(&expr)?;
-        Ok(())
+    // an interpreter that runs pure calls bound with `.as(name)` on pool
+    // until name is read
+    pub fn with_pool(pool: ThreadPool) -> Self {
+        Interpreter { pool: Some(pool.into()), ..Interpreter::new() }
+    }
+
+    // how many payloads this interpreter has copied on write so far
+    pub fn copy_counts(&self) -> CopyCounts {
+        CopyCounts {
+            strings: self.copies.strings.load(Ordering::Relaxed),
+            arrays: self.copies.arrays.load(Ordering::Relaxed),
+            structs: self.copies.structs.load(Ordering::Relaxed),
+        }
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<Value, String> {
-        match expr {
-            Expr::Block(exprs) => {
-                let mut last_value = Value::None;
-                for expr in exprs {
-                    last_value = self.evaluate(expr)?;
+    pub fn interpret(&mut self, ast: &Expr) -> Result<Value, Diagnostic> {
+        let value = match self.evaluate(ast) {
+            Ok(value) | Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(diag)) => return Err(diag),
+        };
+        for pending in self.spawned.drain(..) {
+            pending.wait()?;
+        }
+        Ok(value)
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, Unwind> {
+        match &expr.kind {
+            ExprKind::Block(exprs) => {
+                self.scopes.push(HashMap::new());
+                let mut last_value = Ok(Value::None);
+                for (i, expr) in exprs.iter().enumerate() {
+                    // drop the previous statement's value first, so it doesn't
+                    // keep a payload shared while this one writes to it
+                    drop(last_value);
+                    last_value = if i + 1 < exprs.len() {
+                        self.evaluate_statement(expr)
+                    } else {
+                        self.evaluate(expr)
+                    };
+                    if last_value.is_err() {
+                        break;
+                    }
                 }
-                Ok(last_value)
+                self.scopes.pop();
+                last_value
             }
-            Expr::UnaryOp { op, expr } => {
-                let value = self.evaluate(expr)?;
-                self.evaluate_unary_op(op, value)
+            ExprKind::UnaryOp { op, expr: inner } => {
+                let value = self.evaluate(inner)?;
+                Ok(self.evaluate_unary_op(op, value, expr.span)?)
             }
-            Expr::BinaryOp { left, op, right } => {
+            ExprKind::BinaryOp { left, op, right } => {
+                if let Operator::AssignOp(inner) = &**op {
+                    return self.assign_op(inner, left, right, expr.span);
+                }
                 let left_value = self.evaluate(left)?;
+                // && and || only look at the right side when they have to
+                match (&**op, &left_value) {
+                    (Operator::And, Value::Bool(false)) | (Operator::Or, Value::Bool(true)) => return Ok(left_value),
+                    _ => {}
+                }
                 let right_value = self.evaluate(right)?;
-                self.evaluate_binary_op(op, left_value, right_value)
+                Ok(binary_op(op, left_value, right_value, expr.span)?)
             }
-            Expr::If { condition, then_branch, else_branch } => {
+            ExprKind::If { condition, then_branch, else_branch } => {
                 let condition_value = self.evaluate(condition)?;
-                if self.is_true(&condition_value)? {
+                if self.is_true(&condition_value, condition.span)? {
                     self.evaluate(then_branch)
                 } else {
                     self.evaluate(else_branch)
                 }
             }
-            Expr::Function { param_sig, return_sig, block } => {
-                unimplemented!("Function evaluation is not yet implemented")
+            ExprKind::Function { param_sig, return_sig, block } => {
+                let mut captures = HashMap::new();
+                for scope in &self.scopes {
+                    captures.extend(scope.iter().map(|(name, value)| (name.clone(), value.clone())));
+                }
+                Ok(Value::Function {
+                    param_sig: param_sig.clone(),
+                    return_sig: return_sig.clone(),
+                    block: block.clone(),
+                    captures: captures.into(),
+                })
+            }
+            ExprKind::Array(exprs) => {
+                let values = exprs.iter().map(|e| self.evaluate(e)).collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Array(values.into()))
+            }
+            ExprKind::Struct { pairs } => {
+                // fields are evaluated in the order they are declared, so
+                // side effects happen in the same order on every run
+                let mut pairs: Vec<_> = pairs.iter().collect();
+                pairs.sort_by_key(|(name, _)| field_order(name));
+                let mut values = HashMap::new();
+                for (name, expr) in pairs {
+                    values.insert(name.clone(), self.evaluate(expr)?);
+                }
+                Ok(Value::Struct(values.into()))
+            }
+            ExprKind::Option(Some(inner)) => {
+                let value = self.evaluate(inner)?;
+                Ok(Value::Optional(Some(value.into())))
             }
-            Expr::Type(t) => Ok(Value::Type(t.clone())),
-            Expr::Identifier(ref name) => {
-                if let Some(value) = self.variables.get(&**name) {
-                    Ok(value.clone())
+            ExprKind::Option(None) => Ok(Value::Optional(None)),
+            // `<()>` is the union's None member itself
+            ExprKind::Union(inner) if matches!(inner.kind, ExprKind::Option(None)) => Ok(Value::Optional(None)),
+            ExprKind::Union(inner) => {
+                let value = self.evaluate(inner)?;
+                Ok(Value::Optional(Some(value.into())))
+            }
+            ExprKind::FieldAccess { context, field } => {
+                let value = self.evaluate(context)?;
+                match value {
+                    Value::Struct(pairs) => match pairs.get(&**field) {
+                        Some(value) => Ok(value.clone()),
+                        None => Err(Diagnostic::error(ErrorCode::UnknownField, expr.span, format!("No field `{}` on this value", field)).into()),
+                    },
+                    _ => Err(Diagnostic::error(ErrorCode::InvalidOperand, context.span, format!("Cannot access field `{}` on {}", field, value)).into()),
+                }
+            }
+            ExprKind::MethodCall { name, context, param, .. } => {
+                let context_value = self.evaluate(context)?;
+                let arg = self.evaluate(param)?;
+                if let Value::Optional(value) = context_value {
+                    return self.call_union_method(name, value, arg, expr.span);
+                }
+                Ok(self.call_method(name, context_value, arg, expr.span)?)
+            }
+            ExprKind::Param => {
+                match self.params.last() {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(Diagnostic::error(ErrorCode::InvalidParam, expr.span, "`@` used outside of a function body").into()),
+                }
+            }
+            ExprKind::Call { callee, arg } => {
+                let function = self.evaluate(callee)?;
+                let arg = self.evaluate(arg)?;
+                self.call(function, arg, callee.span)
+            }
+            ExprKind::PassTo { context, param, .. } => {
+                let arg = self.evaluate(context)?;
+                let function = self.evaluate(param)?;
+                self.call(function, arg, param.span)
+            }
+            ExprKind::Assign { context, param, .. } => {
+                let value = self.evaluate(context)?;
+                match param.kind {
+                    ExprKind::Identifier(ref name) => {
+                        if let Some(scope) = self.scopes.last_mut() {
+                            scope.insert(name.to_string(), value.clone());
+                        }
+                        Ok(value)
+                    }
+                    _ => Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, param.span, "Expected an identifier to assign to").into()),
+                }
+            }
+            ExprKind::AssignShared { .. } => Ok(self.share(expr)?.get()),
+            ExprKind::Type(t) => Ok(Value::Type(t.clone())),
+            ExprKind::Identifier(ref name) => {
+                if let Some(value) = self.scopes.iter().rev().find_map(|scope| scope.get(&**name)) {
+                    match value {
+                        Value::Shared(shared) => Ok(shared.get()),
+                        // the implied await
+                        Value::Pending(pending) => Ok(pending.wait()?),
+                        value => Ok(value.clone()),
+                    }
                 } else {
-                    Err(format!("Undefined variable: {}", name))
+                    Err(Diagnostic::error(ErrorCode::UndefinedVariable, expr.span, format!("Undefined variable: {}", name)).into())
                 }
             }
-            Expr::Bool(b) => Ok(Value::Bool(*b)),
-            Expr::Int(i) => Ok(Value::Int(*i)),
-            Expr::Uint(u) => Ok(Value::Uint(*u)),
-            Expr::Char(c) => Ok(Value::Char(*c)),
-            Expr::Float(f) => Ok(Value::Float(*f)),
-            Expr::String(s) => Ok(Value::String(s.clone().deref().clone())),
-            _ => {
-                unimplemented!("engine still in progress sorry")
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Int(i) => Ok(Value::Int(i.clone())),
+            ExprKind::Uint(u) => Ok(Value::Uint(u.clone())),
+            ExprKind::Char(c) => Ok(Value::Char(*c)),
+            ExprKind::Float(f) => Ok(Value::Float(*f)),
+            ExprKind::String(s) => Ok(Value::String(s.clone())),
+            ExprKind::Return(inner) => {
+                let value = self.evaluate(inner)?;
+                Err(Unwind::Return(value))
+            }
+            ExprKind::Mut { expr, .. } => self.evaluate(expr),
+            // a ghost shares the representation of its underlying type
+            ExprKind::Convert { target, expr } => {
+                let value = self.evaluate(expr)?;
+                match target.resolve() {
+                    Type::Ghost { name, .. } => Ok(Value::Ghost { name: name.clone(), value: value.into() }),
+                    _ => Ok(value),
+                }
             }
         }
     }
 
-    fn evaluate_unary_op(&self, op: &Operator, value: Value) -> Result<Value, String> {
-        match op {
-            Operator::Not => {
-                if let Value::Bool(b) = value {
-                    Ok(Value::Bool(!b))
-                } else {
-                    Err(format!("Invalid type for unary operator Not: {:?}", value))
+    // a statement whose value is thrown away; if it binds the result of a
+    // pure call to a name, the call runs on the pool and the name holds a
+    // pending value until it is first read
+    fn evaluate_statement(&mut self, expr: &Expr) -> Result<Value, Unwind> {
+        let (Some(pool), ExprKind::Assign { context, param, .. }) = (&self.pool, &expr.kind) else {
+            return self.evaluate(expr);
+        };
+        let ExprKind::Identifier(ref name) = param.kind else {
+            return self.evaluate(expr);
+        };
+        let pool = pool.clone();
+        let (function, arg, span) = match &context.kind {
+            ExprKind::Call { callee, arg } => (self.evaluate(callee)?, self.evaluate(arg)?, callee.span),
+            ExprKind::PassTo { context, param, .. } => {
+                let arg = self.evaluate(context)?;
+                (self.evaluate(param)?, arg, param.span)
+            }
+            _ => return self.evaluate(expr),
+        };
+        let value = if function.is_pure() && arg.is_pure() {
+            let pending = Arc::new(PendingValue::new());
+            let result = pending.clone();
+            let copies = self.copies.clone();
+            // the call can't see anything another thread writes to, so it
+            // runs the same anywhere. Calls wait in one queue that any free
+            // worker takes the oldest from, and a worker runs everything its
+            // call makes inline, so a call only ever waits on pending values
+            // queued before it, which are already running or done
+            pool.execute(move || {
+                // a panic still has to complete the value, or everything
+                // reading it would wait forever
+                let call = panic::catch_unwind(AssertUnwindSafe(|| {
+                    Interpreter { copies, ..Interpreter::new() }.call(function, arg, span)
+                }));
+                let value = match call {
+                    Ok(Ok(value) | Err(Unwind::Return(value))) => Ok(value),
+                    Ok(Err(Unwind::Error(diag))) => Err(diag),
+                    Err(_) => Err(Diagnostic::error(ErrorCode::CallPanicked, span, "Call running in the background panicked")
+                        .with_primary_label("this call")
+                        .with_note("run with --sequential to see where it panics")),
+                };
+                result.complete(value);
+            });
+            self.spawned.push(pending.clone());
+            Value::Pending(pending)
+        } else {
+            self.call(function, arg, span)?
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+        Ok(Value::None)
+    }
+
+    fn call(&mut self, function: Value, arg: Value, span: Span) -> Result<Value, Unwind> {
+        match function {
+            Value::Function { param_sig, return_sig, block, captures } => {
+                // the body only sees what it captured, not the caller's scopes
+                let caller_scopes = std::mem::replace(&mut self.scopes, vec![captures.deref().clone()]);
+                self.params.push(into_union(arg, &param_sig));
+                let result = self.evaluate(&block);
+                self.params.pop();
+                self.scopes = caller_scopes;
+                match result {
+                    Ok(value) | Err(Unwind::Return(value)) => Ok(into_union(value, &return_sig)),
+                    result => result,
                 }
             }
+            _ => Err(Diagnostic::error(ErrorCode::NotCallable, span, format!("Cannot call a non-function value: {}", function)).into()),
+        }
+    }
+
+    fn evaluate_unary_op(&self, op: &Operator, value: Value, span: Span) -> Result<Value, Diagnostic> {
+        match (op, &value) {
+            (Operator::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+            (Operator::Sub, Value::Int(i)) => Ok(Value::Int(-i)),
+            (Operator::Sub, Value::Float(f)) => Ok(Value::Float(-f)),
+            (Operator::Not | Operator::Sub, _) => {
+                Err(Diagnostic::error(ErrorCode::InvalidOperand, span, format!("Invalid type for unary operator {}: {}", op, value)))
+            }
             _ => {
-                unimplemented!("engine still in progress sorry")
+                Err(Diagnostic::error(ErrorCode::Unsupported, span, format!("Unsupported unary operator: {}", op)))
             }
         }
     }
 
-    fn evaluate_binary_op(&self, op: &Operator, left: Value, right: Value) -> Result<Value, String> {
-        match op {
-            Operator::Add => {
-                match (&left, &right) {
-                    (Value::Int(l), Value::Int(r)) => Ok(Value::Int(l + r)),
-                    (Value::Float(l), Value::Float(r)) => Ok(Value::Float(l + r)),
-                    (Value::String(l), Value::String(r)) => Ok(Value::String(l.to_owned() + r)),
-                    _ => Err(format!("Invalid types for binary operator Add: {:?} and {:?}", left, right)),
-                }
+    // built-in methods; the type checker has already matched name against
+    // Type::method, so anything else here is a bug in one of the two
+    fn call_method(&self, name: &str, context: Value, arg: Value, span: Span) -> Result<Value, Diagnostic> {
+        let unknown = || Diagnostic::error(ErrorCode::UnknownMethod, span, format!("Method '{}' not found on {}", name, context));
+        let (l, r, is_uint) = match (&context, &arg) {
+            (Value::Int(l), Value::Int(r)) => (l, r, false),
+            (Value::Uint(l), Value::Uint(r)) => (l, r, true),
+            _ => return Err(unknown()),
+        };
+        let Some((policy, op)) = name.split_once('_') else {
+            return Err(unknown());
+        };
+        let exact = match op {
+            "add" => l + r,
+            "sub" => l - r,
+            "mul" => l * r,
+            _ => return Err(unknown()),
+        };
+        // plain operators never lose precision; these methods are for code
+        // that wants 64-bit machine integer behaviour instead
+        let value = match (policy, is_uint) {
+            ("wrapping", false) => Value::Int(exact.wrapping_i64().into()),
+            ("wrapping", true) => Value::Uint(exact.wrapping_u64().into()),
+            ("saturating", false) => {
+                let clamped = exact.to_i64().unwrap_or(if exact.is_negative() { i64::MIN } else { i64::MAX });
+                Value::Int(clamped.into())
             }
-            Operator::Sub => {
-                match (&left, &right) {
-                    (Value::Int(l), Value::Int(r)) => Ok(Value::Int(l - r)),
-                    (Value::Float(l), Value::Float(r)) => Ok(Value::Float(l - r)),
-                    _ => Err(format!("Invalid types for binary operator Sub: {:?} and {:?}", left, right)),
-                }
-            }
-            Operator::Mul => {
-                match (&left, &right) {
-                    (Value::Int(l), Value::Int(r)) => Ok(Value::Int(l * r)),
-                    (Value::Float(l), Value::Float(r)) => Ok(Value::Float(l * r)),
-                    _ => Err(format!("Invalid types for binary operator Mul: {:?} and {:?}", left, right)),
-                }
+            ("saturating", true) => {
+                let clamped = exact.to_u64().unwrap_or(if exact.is_negative() { 0 } else { u64::MAX });
+                Value::Uint(clamped.into())
             }
-            Operator::Div => {
-                match (&left, &right) {
-                    (Value::Int(l), Value::Int(r)) => {
-                        if *r == 0 {
-                            Err("Division by zero".to_string())
-                        } else {
-                            Ok(Value::Int(l / r))
-                        }
-                    }
-                    (Value::Float(l), Value::Float(r)) => {
-                        if *r == 0.0 {
-                            Err("Division by zero".to_string())
-                        } else {
-                            Ok(Value::Float(l / r))
-                        }
+            ("checked", false) => Value::Optional(exact.to_i64().map(|i| Value::Int(i.into()).into())),
+            ("checked", true) => Value::Optional(exact.to_u64().map(|u| Value::Uint(u.into()).into())),
+            _ => return Err(unknown()),
+        };
+        Ok(value)
+    }
+
+    // is, then, map, map_or and unwrap_or; the checker made sure the
+    // arguments fit the union
+    fn call_union_method(&mut self, name: &str, value: Option<Box<Value>>, arg: Value, span: Span) -> Result<Value, Unwind> {
+        match (name, value, arg) {
+            ("is", Some(value), Value::Type(t)) if value.has_type(&t) => Ok(Value::Optional(Some(value))),
+            ("is", _, _) => Ok(Value::Optional(None)),
+            ("then", value, function) => {
+                if let (Some(value), Value::Function { param_sig, .. }) = (&value, &function) {
+                    if value.has_type(param_sig) {
+                        self.call(function.clone(), value.deref().clone(), span)?;
                     }
-                    _ => Err(format!("Invalid types for binary operator Div: {:?} and {:?}", left, right)),
                 }
+                Ok(Value::Optional(value))
+            }
+            ("map", Some(value), function) => {
+                let value = self.call(function, *value, span)?;
+                Ok(Value::Optional(Some(value.into())))
+            }
+            ("map", None, _) => Ok(Value::Optional(None)),
+            ("map_or", Some(value), default) if value.same_shape(&default) => Ok(*value),
+            ("unwrap_or", Some(value), _) => Ok(*value),
+            ("map_or" | "unwrap_or", _, default) => Ok(default),
+            (name, value, _) => {
+                let value = Value::Optional(value);
+                Err(Diagnostic::error(ErrorCode::UnknownMethod, span, format!("Method '{}' not found on {}", name, value)).into())
             }
-            _ => Err(format!("Unsupported operator: {:?}", op)),
         }
     }
 
-    fn is_true(&self, value: &Value) -> Result<bool, String> {
+    // `name op= value` and `name.field op= value`; updates the variable in
+    // place, in the scope it lives in
+    fn assign_op(&mut self, op: &Operator, target: &Expr, right: &Expr, span: Span) -> Result<Value, Unwind> {
+        let mut root = match &target.kind {
+            ExprKind::Mut { expr, .. } => expr.deref(),
+            _ => target,
+        };
+        let mut path = Vec::new();
+        while let ExprKind::FieldAccess { context, field } = &root.kind {
+            path.push(field.clone());
+            root = context;
+        }
+        path.reverse();
+        let ExprKind::Identifier(ref name) = root.kind else {
+            return Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, target.span, "Expected an identifier to assign to").into());
+        };
+        let right_value = self.evaluate(right)?;
+        let Some(slot) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&**name)) else {
+            return Err(Diagnostic::error(ErrorCode::UndefinedVariable, root.span, format!("Undefined variable: {}", name)).into());
+        };
+        if let Value::Pending(pending) = slot {
+            *slot = pending.wait()?;
+        }
+        match slot {
+            Value::Shared(shared) => Ok(shared.update(|value| update_path(value, &path, op, right_value, span, &self.copies))?),
+            slot => Ok(update_path(slot, &path, op, right_value, span, &self.copies)?),
+        }
+    }
+
+    // binds the name of an as_shared call to its location, which is the
+    // location of the as_shared call it is chained onto, if any
+    fn share(&mut self, expr: &Expr) -> Result<SharedValue, Unwind> {
+        let ExprKind::AssignShared { context, param } = &expr.kind else {
+            return Ok(SharedValue::new(self.evaluate(expr)?));
+        };
+        let ExprKind::Identifier(ref name) = param.kind else {
+            return Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, param.span, "Expected an identifier to assign to").into());
+        };
+        let shared = self.share(context)?;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Value::Shared(shared.clone()));
+        }
+        Ok(shared)
+    }
+
+    fn is_true(&self, value: &Value, span: Span) -> Result<bool, Diagnostic> {
         match value {
             Value::Bool(b) => Ok(*b),
-            _ => Err(format!("Expected boolean, found: {:?}", value)),
+            _ => Err(Diagnostic::error(ErrorCode::InvalidOperand, span, format!("Expected boolean, found: {}", value))),
+        }
+    }
+}
+
+// the checker lets a plain member value through where its union is
+// expected; at runtime it still has to arrive as a union value
+fn into_union(value: Value, t: &Type) -> Value {
+    match (t.value_type().resolve(), value) {
+        (Type::Optional { .. }, value @ Value::Optional(_)) => value,
+        (Type::Optional { .. }, value) => Value::Optional(Some(value.into())),
+        (_, value) => value,
+    }
+}
+
+fn overflow(op: &Operator, span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::ArithmeticOverflow, span, format!("uint overflow in {} operation", op))
+        .with_primary_label("the result is below zero, which a uint can't hold")
+        .with_note("use an int, or the wrapping_*, saturating_* or checked_* methods for 64-bit behaviour")
+}
+
+fn binary_op(op: &Operator, left: Value, right: Value, span: Span) -> Result<Value, Diagnostic> {
+    // ghosts compute on their underlying values, and arithmetic on them
+    // stays ghosted
+    if let (Value::Ghost { name, value: l }, Value::Ghost { value: r, .. }) = (&left, &right) {
+        let value = binary_op(op, l.deref().clone(), r.deref().clone(), span)?;
+        return Ok(match op {
+            Operator::Eq | Operator::Neq | Operator::Lt | Operator::Gt | Operator::LtEq | Operator::GtEq => value,
+            _ => Value::Ghost { name: name.clone(), value: value.into() },
+        });
+    }
+    let invalid = || {
+        Diagnostic::error(ErrorCode::InvalidOperand, span, format!("Invalid types for binary operator {}: {} and {}", op, left, right))
+    };
+    let division_by_zero = || Diagnostic::error(ErrorCode::DivisionByZero, span, "Division by zero");
+    // same table the type checker uses, so anything it let through is
+    // handled below
+    let supported = match left.operand_type() {
+        Some(t) => t.supports_operator(op),
+        None => matches!(op, Operator::Eq | Operator::Neq),
+    };
+    if !supported {
+        return Err(invalid());
+    }
+    match op {
+        Operator::Eq => return Ok(Value::Bool(left == right)),
+        Operator::Neq => return Ok(Value::Bool(left != right)),
+        Operator::Lt | Operator::Gt | Operator::LtEq | Operator::GtEq => {
+            let result = match (&left, &right) {
+                (Value::Bool(l), Value::Bool(r)) => compare(op, l, r),
+                (Value::Int(l), Value::Int(r)) => compare(op, l, r),
+                (Value::Uint(l), Value::Uint(r)) => compare(op, l, r),
+                (Value::Char(l), Value::Char(r)) => compare(op, l, r),
+                (Value::Float(l), Value::Float(r)) => compare(op, l, r),
+                (Value::String(l), Value::String(r)) => compare(op, l, r),
+                _ => None,
+            };
+            return result.map(Value::Bool).ok_or_else(invalid);
+        }
+        _ => {}
+    }
+    let value = match (&left, &right) {
+        (Value::Int(l), Value::Int(r)) => match integer_op(op, l, r) {
+            Some(Some(i)) => Value::Int(i),
+            Some(None) => return Err(division_by_zero()),
+            None => return Err(invalid()),
+        },
+        // uint is unbounded above, so going below zero is its only overflow
+        (Value::Uint(l), Value::Uint(r)) => match integer_op(op, l, r) {
+            Some(Some(u)) if u.is_negative() => return Err(overflow(op, span)),
+            Some(Some(u)) => Value::Uint(u),
+            Some(None) => return Err(division_by_zero()),
+            None => return Err(invalid()),
+        },
+        (Value::Float(l), Value::Float(r)) => match op {
+            Operator::Add => Value::Float(l + r),
+            Operator::Sub => Value::Float(l - r),
+            Operator::Mul => Value::Float(l * r),
+            Operator::Div | Operator::Mod if *r == 0.0 => return Err(division_by_zero()),
+            Operator::Div => Value::Float(l / r),
+            Operator::Mod => Value::Float(l % r),
+            _ => return Err(invalid()),
+        },
+        (Value::Bool(l), Value::Bool(r)) => match op {
+            Operator::And | Operator::BitAnd => Value::Bool(*l && *r),
+            Operator::Or | Operator::BitOr => Value::Bool(*l || *r),
+            Operator::BitXor => Value::Bool(l ^ r),
+            _ => return Err(invalid()),
+        },
+        (Value::String(l), Value::String(r)) => match op {
+            Operator::Add => Value::String(Arc::new(l.deref().clone() + r)),
+            _ => return Err(invalid()),
+        },
+        (Value::Array(l), Value::Array(r)) => match op {
+            Operator::Add => Value::Array(Arc::new(l.iter().chain(r.iter()).cloned().collect())),
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+// payload copies forced by writes to storage that was still shared, counted
+// per run
+#[derive(Debug, Default)]
+struct CopyCounter {
+    strings: AtomicUsize,
+    arrays: AtomicUsize,
+    structs: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CopyCounts {
+    pub strings: usize,
+    pub arrays: usize,
+    pub structs: usize,
+}
+
+impl fmt::Display for CopyCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} string, {} array and {} struct copies", self.strings, self.arrays, self.structs)
+    }
+}
+
+// unique access to a payload, copying it first when another value still
+// shares it
+fn make_mut<'a, T: Clone>(payload: &'a mut Arc<T>, copies: &AtomicUsize) -> &'a mut T {
+    if Arc::get_mut(payload).is_none() {
+        copies.fetch_add(1, Ordering::Relaxed);
+    }
+    Arc::make_mut(payload)
+}
+
+// applies `op= right` to the field at path inside value, or to value itself
+// when path is empty, and gives back the new value of that field
+fn update_path(value: &mut Value, path: &[Arc<String>], op: &Operator, right: Value, span: Span, copies: &CopyCounter) -> Result<Value, Diagnostic> {
+    let Some((field, rest)) = path.split_first() else {
+        // appending to a string or array can reuse its buffer
+        match (op, &mut *value, &right) {
+            (Operator::Add, Value::String(l), Value::String(r)) => {
+                make_mut(l, &copies.strings).push_str(r);
+                return Ok(value.clone());
+            }
+            (Operator::Add, Value::Array(l), Value::Array(r)) => {
+                make_mut(l, &copies.arrays).extend(r.iter().cloned());
+                return Ok(value.clone());
+            }
+            _ => {}
         }
+        *value = binary_op(op, value.clone(), right, span)?;
+        return Ok(value.clone());
+    };
+    let Value::Struct(pairs) = value else {
+        return Err(Diagnostic::error(ErrorCode::InvalidOperand, span, format!("Cannot access field `{}` on {}", field, value)));
+    };
+    match make_mut(pairs, &copies.structs).get_mut(&**field) {
+        Some(value) => update_path(value, rest, op, right, span, copies),
+        None => Err(Diagnostic::error(ErrorCode::UnknownField, span, format!("No field `{}` on this value", field))),
+    }
+}
+
+// shared with the constant folder so both compute the same results; None
+// when op isn't arithmetic, Some(None) when it divides by zero
+pub(crate) fn integer_op(op: &Operator, l: &BigInt, r: &BigInt) -> Option<Option<BigInt>> {
+    let value = match op {
+        Operator::Add => l + r,
+        Operator::Sub => l - r,
+        Operator::Mul => l * r,
+        Operator::Div => return Some(l.div_rem(r).map(|(q, _)| q)),
+        Operator::Mod => return Some(l.div_rem(r).map(|(_, r)| r)),
+        Operator::BitAnd => l & r,
+        Operator::BitOr => l | r,
+        Operator::BitXor => l ^ r,
+        _ => return None,
+    };
+    Some(Some(value))
+}
+
+// None when op isn't a comparison
+pub(crate) fn compare<T: PartialOrd>(op: &Operator, l: &T, r: &T) -> Option<bool> {
+    let result = match op {
+        Operator::Eq => l == r,
+        Operator::Neq => l != r,
+        Operator::Lt => l < r,
+        Operator::Gt => l > r,
+        Operator::LtEq => l <= r,
+        Operator::GtEq => l >= r,
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::TypeEnv;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // runs source through the whole pipeline and gives back the printed
+    // result and the copies it took
+    fn run(source: &str) -> (String, CopyCounts) {
+        let mut interpreter = Interpreter::new();
+        let value = run_in(&mut interpreter, source).unwrap();
+        (value.to_string(), interpreter.copy_counts())
+    }
+
+    fn run_in(interpreter: &mut Interpreter, source: &str) -> Result<Value, Diagnostic> {
+        let (ast, diagnostics) = Parser::new(Lexer::new(source)).parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        ast.type_check(&mut TypeEnv::new()).unwrap();
+        interpreter.interpret(&ast)
+    }
+
+    #[test]
+    fn shared_writes_split_off_plain_copies() {
+        let (value, _) = run("
+            1.as_shared(var_a).as_shared(var_b).as(var_c);
+            **var_a += var_b;
+            var_a.as(var_d);
+            **var_a += var_b;
+            (var_a, var_b, var_c, var_d);
+        ");
+        assert_eq!(value, "(4, 4, 1, 2)");
+    }
+
+    #[test]
+    fn strings_are_copied_on_first_write_only() {
+        let (value, copies) = run(r#"
+            "a".as_shared(var_a).as(var_c);
+            **var_a += "b";
+            **var_a += "c";
+            (var_a, var_c);
+        "#);
+        assert_eq!(value, r#"("abc", "a")"#);
+        assert_eq!(copies, CopyCounts { strings: 1, arrays: 0, structs: 0 });
+    }
+
+    #[test]
+    fn arrays_are_copied_on_first_append_only() {
+        let (value, copies) = run("
+            *[1, 2].as(xs);
+            *xs += [3];
+            xs.as(before);
+            *xs += [4];
+            *xs += [5];
+            (xs, before);
+        ");
+        assert_eq!(value, "([1, 2, 3, 4, 5], [1, 2, 3])");
+        assert_eq!(copies, CopyCounts { strings: 0, arrays: 1, structs: 0 });
+    }
+
+    #[test]
+    fn unshared_structs_are_written_in_place() {
+        let (value, copies) = run("
+            *{x: 1, y: 2,}.as(point);
+            *point.x += 1;
+            *point.y += 1;
+            point;
+        ");
+        assert_eq!(value, "{ x: 2, y: 3 }");
+        assert_eq!(copies, CopyCounts { strings: 0, arrays: 0, structs: 0 });
+
+        let (value, copies) = run("
+            *{x: 1, y: 2,}.as(point);
+            point.as(before);
+            *point.x += 1;
+            *point.y += 1;
+            (point, before);
+        ");
+        assert_eq!(value, "({ x: 2, y: 3 }, { x: 1, y: 2 })");
+        assert_eq!(copies, CopyCounts { strings: 0, arrays: 0, structs: 1 });
+    }
+
+    #[test]
+    fn pooled_calls_match_sequential_ones() {
+        let source = "
+            |int -> int| {
+                *0.as(sum);
+                *sum += @ * @;
+                *sum += @;
+                sum
+            }.as(work);
+            work(3).as(a);
+            4.pass_to(work).as(b);
+            work(a).as(c);
+            0.as_shared(total);
+            |int -> int| { **total += @; total }.as(add);
+            add(a).as(d);
+            *0.as(e);
+            *e += b;
+            *c += 1;
+            (a, b, c, d, e, total);
+        ";
+        let sequential = run_in(&mut Interpreter::new(), source).unwrap();
+        for _ in 0..20 {
+            let mut pooled = Interpreter::with_pool(ThreadPool::new(4));
+            assert_eq!(run_in(&mut pooled, source).unwrap(), sequential);
+        }
+        assert_eq!(sequential.to_string(), "(12, 20, 157, 12, 20, 12)");
+    }
+
+    #[test]
+    fn errors_in_unread_pooled_calls_are_reported() {
+        let source = "
+            |int -> int| { 1 / @ }.as(inv);
+            inv(0).as(never_read);
+            5;
+        ";
+        let error = run_in(&mut Interpreter::with_pool(ThreadPool::new(2)), source).unwrap_err();
+        assert_eq!(error.code, ErrorCode::DivisionByZero);
+        let error = run_in(&mut Interpreter::new(), source).unwrap_err();
+        assert_eq!(error.code, ErrorCode::DivisionByZero);
+    }
+
+    #[test]
+    fn plain_values_become_union_values_at_call_boundaries() {
+        let (value, _) = run("
+            |int -> <int | ()>| { @ }(1).as(x);
+            |<int | String> -> int| { @.is(int).map_or(0) }.as(g);
+            (x, x.is(int).map_or(0), g(5));
+        ");
+        assert_eq!(value, "(Some(1), 1, 5)");
+    }
+
+    #[test]
+    fn calls_bind_their_argument() {
+        let (value, _) = run("
+            |{a: int, b: int,} -> int| { @.a + @.b }.as(add);
+            |int -> int| { @ * 2 }.as(double);
+            (add({a: 1, b: 2,}), double(double(3)), 4.pass_to(double));
+        ");
+        assert_eq!(value, "(3, 12, 8)");
+    }
+
+    #[test]
+    fn returns_unwind_out_of_nested_blocks() {
+        let (value, _) = run("
+            |int -> int| {
+                if @ > 0 {
+                    if @ > 5 {
+                        return 2;
+                    };
+                    return 1;
+                };
+                0
+            }.as(sign);
+            (sign(9), sign(3), sign(-4));
+        ");
+        assert_eq!(value, "(2, 1, 0)");
+    }
+
+    #[test]
+    fn param_is_bound_per_call() {
+        let (value, _) = run("
+            |int -> int| { |int -> int| { @ * 10 }(@ + 1) + @ }(1);
+        ");
+        assert_eq!(value, "21");
+    }
+
+    #[test]
+    fn closures_capture_plain_values_by_copy_and_shared_ones_by_reference() {
+        let (value, _) = run("
+            *1.as(x);
+            |int -> int| { x + @ }.as(f);
+            *x += 10;
+            0.as_shared(c);
+            |int -> int| { **c += @; c }.as(add);
+            add(2);
+            add(3);
+            (f(1), x, c);
+        ");
+        assert_eq!(value, "(2, 11, 5)");
+    }
+
+    #[test]
+    fn uint_underflow_is_an_error_unless_a_policy_is_picked() {
+        let mut interpreter = Interpreter::new();
+        let error = run_in(&mut interpreter, "1u - 2u;").unwrap_err();
+        assert_eq!(error.code, ErrorCode::ArithmeticOverflow);
+
+        let (value, _) = run("
+            (1u.wrapping_sub(2u), 1u.saturating_sub(2u), 1u.checked_sub(2u), 9223372036854775807.checked_add(1), 9223372036854775807 + 1);
+        ");
+        assert_eq!(value, "(18446744073709551615u, 0u, None, None, 9223372036854775808)");
+    }
+
+    #[test]
+    fn union_methods_only_run_on_the_matching_member() {
+        let (value, _) = run("
+            |int -> <int | bool>| { if @ == 0 { return <true>; }; <@ * 10> }.as(f);
+            2.pass_to(f).as(a);
+            0.pass_to(f).as(b);
+            0.as_shared(ran);
+            a.then(|int -> int| { **ran += 1; @ }).then(|bool -> bool| { **ran += 100; @ });
+            (a.is(int), b.is(int), a.map_or(0), b.map_or(0), b.is(int).unwrap_or(-1), ran);
+        ");
+        assert_eq!(value, "(Some(20), None, 20, 0, -1, 1)");
     }
 }
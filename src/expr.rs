@@ -2,17 +2,28 @@ use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
 
-use crate::type_def::Type;
+use crate::bignum::BigInt;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::span::Span;
+use crate::type_def::{field_order, Type};
 
 use super::lexer::Operator;
 use super::lexer::Token;
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
     Type(Type),
     Bool(bool),
     Option(Option<Arc<Expr>>),
-    Int(i64),
-    Uint(u64),
+    // `<expr>`, a value put into a union
+    Union(Arc<Expr>),
+    Int(BigInt),
+    Uint(BigInt),
     Char(char),
     Float(f64),
     String(Arc<String>),
@@ -53,105 +64,344 @@ pub enum Expr {
         param: Arc<Expr>,
         type_def: Option<Arc<Type>>
     },
+    // `expr.as_shared(name)`; chained as_shared calls all name the same
+    // location
+    AssignShared {
+        context: Arc<Expr>,
+        param: Arc<Expr>,
+    },
     PassTo{
         context: Arc<Expr>,
         param: Arc<Expr>,
         type_def: Option<Arc<Type>>
+    },
+    Call {
+        callee: Arc<Expr>,
+        arg: Arc<Expr>,
+    },
+    FieldAccess {
+        context: Arc<Expr>,
+        field: Arc<String>,
+    },
+    Return(Arc<Expr>),
+    // `*expr` or `**expr`, marking a mutation site or a mutable declaration
+    Mut {
+        expr: Arc<Expr>,
+        shared: bool,
+    },
+    // `Name::from(expr)`, wrapping a value into the ghost type Name
+    Convert {
+        target: Arc<Type>,
+        expr: Arc<Expr>,
+    },
+}
+
+// type of a union method call, and the member it narrowed the union to
+type UnionMethod = (Type, Option<Arc<Type>>);
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub type_def: Type,
+    pub span: Span,
+    // union members this variable was narrowed to with is, then or map
+    pub handled: Vec<Arc<Type>>,
+    // used as a whole somewhere, which hands every member on to someone else
+    pub escaped: bool,
+}
+
+impl Binding {
+    pub fn new(type_def: Type, span: Span) -> Binding {
+        Binding { type_def, span, handled: Vec::new(), escaped: false }
+    }
+}
+
+#[derive(Debug)]
+struct FunctionSig {
+    param_type: Arc<Type>,
+    return_type: Arc<Type>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    vars: HashMap<Arc<String>, Binding>,
+    // set on the outermost scope of a function body
+    function: Option<FunctionSig>,
+}
+
+// chain of lexical scopes, innermost last
+#[derive(Debug)]
+pub struct TypeEnv {
+    scopes: Vec<Scope>,
+    // warnings found while checking
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeEnv {
+    pub fn new() -> TypeEnv {
+        TypeEnv { scopes: vec![Scope::default()], diagnostics: Vec::new() }
+    }
+
+    fn push_scope(&mut self, function: Option<FunctionSig>) {
+        self.scopes.push(Scope { vars: HashMap::new(), function });
+    }
+
+    fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        // a union that was taken apart with is/then/map has to deal with
+        // every member; None is the absence of a value and needs no handling
+        let mut vars: Vec<_> = scope.vars.into_iter().collect();
+        vars.sort_by_key(|(_, binding)| binding.span.start);
+        for (name, binding) in vars {
+            let Type::Optional { members } = binding.type_def.value_type().resolve() else {
+                continue;
+            };
+            if binding.escaped || binding.handled.is_empty() {
+                continue;
+            }
+            for member in members {
+                if **member != Type::None && !binding.handled.contains(member) {
+                    self.diagnostics.push(
+                        Diagnostic::warning(ErrorCode::UnhandledMember, binding.span, format!("`{}` never handles its {} member", name, member))
+                            .with_primary_label(format!("this is {}", binding.type_def))
+                            .with_note(format!("use `.is({})` or `.then(..)` to handle it, or `.map_or(..)` to give a default", member)),
+                    );
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, name: Arc<String>, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.vars.insert(name, binding);
+        }
+    }
+
+    // closest binding for name, and whether it had to be captured from
+    // outside of the function currently being checked
+    fn lookup(&self, name: &String) -> Option<(&Binding, bool)> {
+        let mut captured = false;
+        for scope in self.scopes.iter().rev() {
+            if let Some(binding) = scope.vars.get(name) {
+                return Some((binding, captured));
+            }
+            captured |= scope.function.is_some();
+        }
+        None
+    }
+
+    fn lookup_mut(&mut self, name: &String) -> Option<&mut Binding> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.vars.get_mut(name))
+    }
+
+    // signature of the innermost function being checked
+    fn function(&self) -> Option<&FunctionSig> {
+        self.scopes.iter().rev().find_map(|scope| scope.function.as_ref())
     }
 }
-type TypeEnv = HashMap<Arc<String>, Type>;
+
 impl Expr {
-    pub fn type_check(&self, env: &mut TypeEnv) -> Result<Type, String> {
-        match self {
-            Expr::Type(t) => Ok(t.clone()),
-            Expr::Bool(_) => Ok(Type::Bool),
-            Expr::Option(opt_expr) => {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+
+    pub fn binary(left: Arc<Expr>, op: Arc<Operator>, right: Arc<Expr>) -> Expr {
+        let span = left.span.to(right.span);
+        Expr::new(ExprKind::BinaryOp { left, op, right }, span)
+    }
+
+    pub fn type_check(&self, env: &mut TypeEnv) -> Result<Type, Diagnostic> {
+        self.check_expected(env, None)
+    }
+
+    // expected is the type the surrounding context wants, when it is known;
+    // it only guides inference, callers still compare the result themselves
+    pub fn check_expected(&self, env: &mut TypeEnv, expected: Option<&Type>) -> Result<Type, Diagnostic> {
+        match &self.kind {
+            ExprKind::Type(t) => Ok(t.clone()),
+            ExprKind::Bool(_) => Ok(Type::Bool),
+            ExprKind::Option(opt_expr) => {
                 if let Some(expr) = opt_expr {
-                    let expr_type = expr.type_check(env)?;
-                    Ok(Type::Optional {
-                        type_def: Arc::new(expr_type),
-                    })
+                    let inner_expected = expected.and_then(Type::optional_of).map(Arc::deref);
+                    let expr_type = expr.check_expected(env, inner_expected)?;
+                    Ok(Type::optional(expr_type.into()))
                 } else {
-                    Err("Option must contain an expression.".to_string())
-                }
-            }
-            Expr::Int(_) => Ok(Type::Int),
-            Expr::Uint(_) => Ok(Type::Uint),
-            Expr::Char(_) => Ok(Type::Char),
-            Expr::Float(_) => Ok(Type::Float),
-            Expr::String(_) => Ok(Type::String),
-            Expr::Array(arr) => {
-                let mut element_type = None;
-                for expr in arr {
-                    let expr_type = expr.type_check(env)?;
-                    match &element_type {
-                        None => element_type = Some(expr_type),
-                        Some(t) if *t != expr_type => {
-                            return Err("Array elements must be of the same type.".to_string());
+                    match expected {
+                        Some(t) if matches!(t.resolve(), Type::Optional { members } if members.contains(&Type::None.into())) => Ok(t.clone()),
+                        Some(t) if matches!(t.resolve(), Type::Optional { .. }) => {
+                            Err(Diagnostic::error(ErrorCode::EmptyOption, self.span, "Option must contain an expression."))
+                        }
+                        // `()` on its own is the None value
+                        _ => Ok(Type::None),
+                    }
+                }
+            }
+            ExprKind::Union(expr) => {
+                let members = match expected.map(Type::resolve) {
+                    Some(Type::Optional { members }) => Some(members),
+                    _ => None,
+                };
+                // `<()>` is the None member of the union it goes into
+                if let ExprKind::Option(None) = expr.kind {
+                    return match (expected, members) {
+                        (Some(expected), Some(members)) if !members.contains(&Type::None.into()) => {
+                            Err(Diagnostic::error(ErrorCode::TypeMismatch, expr.span, format!("None is not a member of {}", expected))
+                                .with_primary_label(format!("expected one of {}, found None", expected)))
+                        }
+                        (Some(expected), Some(_)) => Ok(expected.clone()),
+                        _ => Ok(Type::Optional { members: vec![Type::None.into()] }),
+                    };
+                }
+                // a union with a single member besides None tells the value
+                // what it has to be, e.g. `<[]>` for `<[int] | None>`, as
+                // long as the value has that member's shape
+                let hint = members.and_then(|members| {
+                    let mut values = members.iter().filter(|t| ***t != Type::None);
+                    match (values.next(), values.next()) {
+                        (Some(t), None) => Some(t.deref()),
+                        _ => None,
+                    }
+                });
+                let hint = hint.filter(|t| match (&expr.kind, t.resolve()) {
+                    (ExprKind::Array(_), Type::Array { .. })
+                    | (ExprKind::Struct { .. }, Type::Struct { .. })
+                    | (ExprKind::Option(Some(_)), Type::Optional { .. }) => true,
+                    (ExprKind::Array(_) | ExprKind::Struct { .. } | ExprKind::Option(_), _) => false,
+                    _ => true,
+                });
+                let expr_type = expr.check_expected(env, hint)?;
+                match (expected, members) {
+                    (Some(expected), Some(members)) => {
+                        if members.iter().any(|t| t.accepts(&expr_type)) {
+                            Ok(expected.clone())
+                        } else {
+                            Err(Diagnostic::error(ErrorCode::TypeMismatch, expr.span, format!("{} is not a member of {}", expr_type, expected))
+                                .with_primary_label(format!("expected one of {}, found {}", expected, expr_type)))
                         }
-                        _ => {}
                     }
+                    _ => Ok(Type::Optional { members: vec![expr_type.into()] }),
                 }
-                Ok(Type::Array {
-                    array_type: Arc::new(element_type.unwrap_or(Type::None)),
-                })
             }
-            Expr::Struct { pairs } => {
-                let mut struct_pairs = Vec::new();
-                for (key, expr) in pairs {
-                    let expr_type = expr.type_check(env)?;
-                    struct_pairs.push(Arc::new(expr_type));
+            ExprKind::Int(_) => Ok(Type::Int),
+            ExprKind::Uint(_) => Ok(Type::Uint),
+            ExprKind::Char(_) => Ok(Type::Char),
+            ExprKind::Float(_) => Ok(Type::Float),
+            ExprKind::String(_) => Ok(Type::String),
+            ExprKind::Array(_) => {
+                let array_type = self.array_type(env, expected)?;
+                if array_type.contains_unknown() {
+                    Err(Diagnostic::error(ErrorCode::CannotInfer, self.span, "Cannot infer the element type of an empty array")
+                        .with_primary_label("type must be known at this point")
+                        .with_note("use the array where its type is known, e.g. as a function argument or return value"))
+                } else {
+                    Ok(array_type)
                 }
-                Ok(Type::Struct { pairs: struct_pairs })
             }
-            Expr::Identifier(name) => {
-                if let Some(t) = env.get(name) {
-                    Ok(t.clone())
+            ExprKind::Struct { pairs } => {
+                let mut struct_pairs = Vec::new();
+                for (name, expr) in pairs {
+                    let expected = expected.and_then(|t| t.field(name)).map(Arc::deref);
+                    let expr_type = expr.check_expected(env, expected)?;
+                    struct_pairs.push((name, expr_type));
+                }
+                // named fields first, then tuple positions in order
+                struct_pairs.sort_by(|(a, _), (b, _)| field_order(a).cmp(&field_order(b)));
+                let pairs = struct_pairs
+                    .into_iter()
+                    .map(|(name, t)| Type::TypeDef { name: name.clone().into(), type_def: t.into() }.into())
+                    .collect();
+                Ok(Type::Struct { pairs })
+            }
+            ExprKind::Identifier(name) => {
+                if let Some(binding) = env.lookup_mut(name) {
+                    binding.escaped = true;
+                    // reading a variable gives its value, mutable or not
+                    Ok(binding.type_def.value_type().clone())
                 } else {
-                    Err(format!("Undefined identifier: {}", name))
+                    Err(Diagnostic::error(ErrorCode::UndefinedIdentifier, self.span, format!("Undefined identifier: {}", name))
+                        .with_primary_label("not found in this scope"))
                 }
             }
-            Expr::BinaryOp { left, op, right } => {
-                let left_type = Type::reduce(left.type_check(env)?.into());
-                let right_type = Type::reduce(right.type_check(env)?.into());
-                if left_type==right_type {
-                    Ok(left_type.clone().deref().clone()) // TODO <-- fix this
-                } else {
-                    Err("Type mismatch in binary operation.".to_string())
+            ExprKind::BinaryOp { left, op, right } => {
+                if let Operator::AssignOp(_) = **op {
+                    left.check_mutation(env)?;
+                }
+                let left_type = Type::reduce(left.type_check(env)?.value_type().clone().into());
+                let right_type = Type::reduce(right.type_check(env)?.value_type().clone().into());
+                if left_type!=right_type {
+                    return Err(Diagnostic::error(ErrorCode::TypeMismatch, self.span, "Type mismatch in binary operation.")
+                        .with_label(left.span, format!("this is {}", left_type))
+                        .with_label(right.span, format!("this is {}", right_type)))
+                }
+                let applied = match &**op {
+                    Operator::AssignOp(inner) => inner,
+                    op => op,
+                };
+                if !left_type.supports_operator(applied) {
+                    let diag = match applied {
+                        Operator::And | Operator::Or => Diagnostic::error(ErrorCode::TypeMismatch, self.span, "Logical operators expect bool operands."),
+                        _ => Diagnostic::error(ErrorCode::TypeMismatch, self.span, format!("Operator `{}` is not defined for {}", applied, left_type)),
+                    };
+                    return Err(diag.with_label(left.span, format!("this is {}", left_type)));
+                }
+                match **op {
+                    Operator::Eq | Operator::Neq | Operator::Lt | Operator::Gt | Operator::LtEq | Operator::GtEq => {
+                        Ok(Type::Bool)
+                    }
+                    _ => Ok(left_type.deref().clone()),
                 }
             }
-            Expr::UnaryOp { op, expr } => {
+            ExprKind::UnaryOp { op, expr } => {
                 let expr_type = expr.type_check(env)?;
-                match (op.as_ref(), expr_type) {
-                    _ => Err("Type mismatch in unary operation.".to_string()),
+                let wants_uint = expected.map(Type::resolve) == Some(&Type::Uint);
+                if **op == Operator::Sub && (expr_type == Type::Uint || wants_uint && expr.is_primative()) {
+                    return Err(Diagnostic::error(ErrorCode::NegativeUnsigned, self.span, "Negative value used as uint")
+                        .with_primary_label("uint values can't be negative")
+                        .with_note("use an int if the value may be negative"));
+                }
+                match **op {
+                    Operator::Not if expr_type == Type::Bool => Ok(expr_type),
+                    Operator::Sub if expr_type == Type::Int || expr_type == Type::Float => Ok(expr_type),
+                    _ => {
+                        Err(Diagnostic::error(ErrorCode::TypeMismatch, self.span, "Type mismatch in unary operation.")
+                            .with_label(expr.span, format!("this is {}", expr_type)))
+                    }
                 }
             }
-            Expr::If {
+            ExprKind::If {
                 condition,
                 then_branch,
                 else_branch,
             } => {
                 let condition_type = condition.type_check(env)?;
                 if condition_type != Type::Bool {
-                    return Err("Condition of 'if' must be a boolean.".to_string());
+                    return Err(Diagnostic::error(ErrorCode::InvalidCondition, condition.span, "Condition of 'if' must be a boolean.")
+                        .with_primary_label(format!("expected bool, found {}", condition_type)));
                 }
-                let then_type = then_branch.type_check(env)?;
-                let else_type = else_branch.type_check(env)?;
-                if then_type == else_type {
-                    Ok(then_type)
+                let then_type = then_branch.check_expected(env, expected)?;
+                let else_type = else_branch.check_expected(env, expected.or(Some(&then_type)))?;
+                if let Some(t) = then_type.unify(&else_type) {
+                    Ok(t)
                 } else {
-                    Err("Type mismatch in 'if' branches.".to_string())
+                    Err(Diagnostic::error(ErrorCode::TypeMismatch, self.span, "Type mismatch in 'if' branches.")
+                        .with_label(then_branch.span, format!("this is {}", then_type))
+                        .with_label(else_branch.span, format!("this is {}", else_type)))
                 }
             }
-            Expr::Function {
+            ExprKind::Function {
                 param_sig,
-                ref return_sig,
+                return_sig,
                 block,
             } => {
-                let mut env = HashMap::new();
-                let block_type = block.type_check(&mut env)?;
-                if block_type != **return_sig{
-                    Err("Function block does not return correct type".to_string())
+                // the body gets its own `@`, shadowing the one of any enclosing function
+                env.push_scope(Some(FunctionSig { param_type: param_sig.clone(), return_type: return_sig.clone() }));
+                let block_type = block.check_expected(env, Some(return_sig));
+                env.pop_scope();
+                let block_type = block_type?;
+                if !return_sig.accepts(&block_type) {
+                    Err(Diagnostic::error(ErrorCode::ReturnTypeMismatch, block.span, "Function block does not return correct type")
+                        .with_primary_label(format!("expected {}, found {}", return_sig, block_type)))
                 } else {
                     Ok(Type::Function {
                         param_type: param_sig.clone(), // Placeholder for now
@@ -159,89 +409,394 @@ impl Expr {
                     })
                 }
             }
-            Expr::Block(exprs) => {
-                let mut last_type = Type::None;
-                for expr in exprs {
-                    last_type = expr.type_check(env)?;
+            ExprKind::Block(exprs) => {
+                env.push_scope(None);
+                let mut last_type = Ok(Type::None);
+                for (i, expr) in exprs.iter().enumerate() {
+                    let expected = if i + 1 == exprs.len() { expected } else { None };
+                    last_type = expr.check_expected(env, expected);
+                    if last_type.is_err() {
+                        break;
+                    }
                 }
-                Ok(last_type)
+                env.pop_scope();
+                last_type
             }
-            Expr::Param => Err("Params should not be type-checked directly.".to_string()),
-            Expr::MethodCall {
+            ExprKind::Param => match env.function() {
+                Some(function) => Ok(function.param_type.deref().clone()),
+                None => Err(Diagnostic::error(ErrorCode::InvalidParam, self.span, "`@` used outside of a function body")
+                    .with_primary_label("there is no parameter here")),
+            },
+            ExprKind::MethodCall {
                 name,
                 context,
                 param,
                 type_def,
             } => {
-                let context_type = context.type_check(env)?;
+                let context_type = match (&context.kind, name.as_str()) {
+                    // narrowing a variable doesn't use it as a whole
+                    (ExprKind::Identifier(var), "is" | "then" | "map") => match env.lookup(var) {
+                        Some((binding, _)) => binding.type_def.value_type().clone(),
+                        None => context.type_check(env)?,
+                    },
+                    _ => context.type_check(env)?,
+                };
                 assert!(name.as_str()!="as", "as keyword found as method call");
-                let param = context.type_check(env)?;
+                if let Type::Optional { members } = context_type.resolve() {
+                    if let Some((return_type, narrowed)) = Expr::check_union_method(env, name, &context_type, members, param)? {
+                        if let (Some(member), Some(var)) = (narrowed, context.narrowed_var()) {
+                            if let Some(binding) = env.lookup_mut(var) {
+                                binding.handled.push(member);
+                            }
+                        }
+                        return Ok(return_type);
+                    }
+                }
+                if let Some(return_type) = context_type.method(name) {
+                    let param_type = param.check_expected(env, Some(&context_type))?;
+                    if param_type != context_type {
+                        return Err(Diagnostic::error(ErrorCode::TypeMismatch, param.span, format!("Argument of '{}' does not match its context", name))
+                            .with_primary_label(format!("expected {}, found {}", context_type, param_type))
+                            .with_label(context.span, format!("this is {}", context_type)));
+                    }
+                    return Ok(return_type);
+                }
                 match type_def {
                     Some(t) => Ok(t.as_ref().clone()),
-                    None => Err(format!(
-                        "Method '{}' not found in context type '{:?}'",
+                    None => Err(Diagnostic::error(ErrorCode::UnknownMethod, self.span, format!(
+                        "Method '{}' not found in context type '{}'",
                         name, context_type
-                    )),
+                    )).with_label(context.span, format!("this is {}", context_type))),
                 }
             },    
-            Expr::Assign { context, param, type_def } => {
+            ExprKind::Assign { context, param, type_def: _ } => {
                 let context_type = context.type_check(env)?;
-                match **param {
-                    Expr::Identifier(ref var_name) => {
-                        env.insert(var_name.clone(), context_type.clone());
+                match param.kind {
+                    ExprKind::Identifier(ref var_name) => {
+                        // `as` always binds a value of its own, even when
+                        // chained onto as_shared
+                        env.declare(var_name.clone(), Binding::new(context_type.value_type().clone(), param.span));
                         Ok(context_type)
                     }
                     _ => {
-                        return Err(format!(
-                            "Invalid parameter for 'as': expected identifier, found {:?}",
-                            param
-                        ));
+                        Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, param.span, format!(
+                            "Invalid parameter for 'as': expected identifier, found {}",
+                            param.primative_str()
+                        )).with_primary_label("expected an identifier")
+                          .with_note("variables are declared with `<expr>.as(<name>)`"))
+                    }
+                }
+            }
+            ExprKind::AssignShared { context, param } => {
+                let context_type = context.type_check(env)?;
+                let ExprKind::Identifier(ref var_name) = param.kind else {
+                    return Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, param.span, "Invalid parameter for 'as_shared': expected identifier")
+                        .with_primary_label("expected an identifier"));
+                };
+                let shared_type = Type::Mut { type_def: context_type.value_type().clone().into(), shared: true };
+                env.declare(var_name.clone(), Binding::new(shared_type.clone(), param.span));
+                Ok(shared_type)
+            }
+            ExprKind::Call { callee, arg } => {
+                let callee_type = callee.type_check(env)?;
+                match callee_type.resolve() {
+                    Type::Function { param_type, return_type } => {
+                        let arg_type = arg.check_expected(env, Some(param_type))?;
+                        if param_type.accepts(&arg_type) {
+                            Ok(return_type.deref().clone())
+                        } else {
+                            Err(Diagnostic::error(ErrorCode::TypeMismatch, arg.span, "Argument does not match the function's parameter type")
+                                .with_primary_label(format!("expected {}, found {}", param_type, arg_type))
+                                .with_label(callee.span, format!("this takes {}", param_type)))
+                        }
+                    }
+                    _ => {
+                        Err(Diagnostic::error(ErrorCode::NotCallable, callee.span, format!("Cannot call a value of type {}", callee_type))
+                            .with_primary_label("not a function"))
                     }
                 }
             }
-            Expr::PassTo { context, param, type_def } => {
-                let context_type = Type::reduce(context.type_check(env)?.into());
+            ExprKind::PassTo { context, param, type_def: _ } => {
+                let context_type = context.type_check(env)?;
                 let param_type = param.type_check(env)?;
-                match param_type {
+                match param_type.resolve() {
                     Type::Function { param_type, return_type } => {
-                        let param_type = Type::reduce(param_type);
-                        if param_type==context_type {
+                        if param_type.accepts(&context_type) {
                             Ok(return_type.clone().deref().clone())
                         } else {
-                            Err(format!(
-                                "Invalid parameter type for 'pass_to': expected function, found {:?}",
-                                param
-                            ))
+                            Err(Diagnostic::error(ErrorCode::InvalidPassTo, self.span, "Invalid parameter type for 'pass_to'")
+                                .with_label(context.span, format!("this is {}", context_type))
+                                .with_label(param.span, format!("function expects {}", param_type)))
                         }
                     }
                     _ => {
-                        Err(format!(
-                            "Invalid parameter for 'pass_to': expected function, found {:?}",
-                            param
-                        ))
+                        Err(Diagnostic::error(ErrorCode::InvalidPassTo, param.span, format!(
+                            "Invalid parameter for 'pass_to': expected function, found {}",
+                            param_type
+                        )).with_primary_label("expected a function"))
+                    }
+                }
+            }
+            ExprKind::Return(expr) => {
+                let Some(return_type) = env.function().map(|f| f.return_type.clone()) else {
+                    return Err(Diagnostic::error(ErrorCode::ReturnOutsideFunction, self.span, "`return` outside of a function body")
+                        .with_primary_label("there is no function to return from"));
+                };
+                let expr_type = expr.check_expected(env, Some(&return_type))?;
+                if !return_type.accepts(&expr_type) {
+                    return Err(Diagnostic::error(ErrorCode::ReturnTypeMismatch, expr.span, "Returned value does not match the function's return type")
+                        .with_primary_label(format!("expected {}, found {}", return_type, expr_type)));
+                }
+                Ok(Type::Never)
+            }
+            ExprKind::Mut { expr, shared } => {
+                let value_type = expr.check_expected(env, expected.map(Type::value_type))?.value_type().clone();
+                // `*<expr>.as(name)` declares a mutable variable
+                if let ExprKind::Assign { param, .. } = &expr.kind {
+                    if *shared {
+                        return Err(Diagnostic::error(ErrorCode::InvalidMutation, self.span, "Shared variables can't be declared with `**`")
+                            .with_primary_label("declares a variable")
+                            .with_note("declare shared variables with `as_shared`"));
+                    }
+                    if let ExprKind::Identifier(name) = &param.kind {
+                        if let Some(binding) = env.lookup_mut(name) {
+                            binding.type_def = Type::Mut { type_def: value_type.clone().into(), shared: false };
+                        }
                     }
                 }
+                Ok(Type::Mut { type_def: value_type.into(), shared: *shared })
+            }
+            ExprKind::Convert { target, expr } => {
+                let Type::Ghost { type_def, .. } = target.resolve() else {
+                    return Err(Diagnostic::error(ErrorCode::InvalidConversion, self.span, format!("Cannot convert into {}", target))
+                        .with_primary_label("not a ghost type")
+                        .with_note(format!("{} is a transparent alias; its values can be used directly", target)));
+                };
+                let expr_type = expr.check_expected(env, Some(type_def))?;
+                if !type_def.accepts(&expr_type) {
+                    return Err(Diagnostic::error(ErrorCode::InvalidConversion, expr.span, format!("Cannot convert {} into {}", expr_type, target))
+                        .with_primary_label(format!("expected {}, found {}", type_def, expr_type))
+                        .with_note(format!("{} is a ghost of {}", target, type_def)));
+                }
+                Ok(target.deref().clone())
             }
+            ExprKind::FieldAccess { context, field } => {
+                let context_type = context.type_check(env)?;
+                if let Some(t) = context_type.field(field) {
+                    return Ok(t.deref().clone());
+                }
+                let err = Diagnostic::error(ErrorCode::UnknownField, self.span, format!("No field `{}` on type {}", field, context_type))
+                    .with_label(context.span, format!("this is {}", context_type));
+                match context_type.resolve() {
+                    Type::Struct { pairs } if pairs.is_empty() => Err(err.with_note("this type has no fields")),
+                    Type::Struct { pairs } => {
+                        let fields: Vec<String> = pairs
+                            .iter()
+                            .map(|pair| match &**pair {
+                                Type::TypeDef { name, .. } => format!("`{}`", name),
+                                t => format!("`{}`", t),
+                            })
+                            .collect();
+                        Err(err.with_note(format!("available fields are: {}", fields.join(", "))))
+                    }
+                    _ => Err(err.with_note("only structs and tuples have fields")),
+                }
+            }
+        }
+    }
+    // built-in methods of unions and optionals, Ok(None) if name isn't one
+    // of them; also gives the member the union got narrowed to, if any
+    fn check_union_method(
+        env: &mut TypeEnv,
+        name: &str,
+        context_type: &Type,
+        members: &[Arc<Type>],
+        param: &Expr,
+    ) -> Result<Option<UnionMethod>, Diagnostic> {
+        let not_a_member = |t: &Type, span: Span| {
+            let names: Vec<String> = members.iter().map(|t| format!("`{}`", t)).collect();
+            Diagnostic::error(ErrorCode::TypeMismatch, span, format!("{} is not a member of {}", t, context_type))
+                .with_primary_label(format!("expected one of {}", context_type))
+                .with_note(format!("members are: {}", names.join(", ")))
+        };
+        let optional_value = || {
+            context_type.optional_of().ok_or_else(|| {
+                Diagnostic::error(ErrorCode::TypeMismatch, param.span, format!("'{}' needs an optional ?T, found {}", name, context_type))
+                    .with_note("narrow the union to one member with `.is(T)` first")
+            })
+        };
+        let result = match name {
+            // `<..>.is(T)` is `?T`, holding the value only when it is a T
+            "is" => {
+                let ExprKind::Type(t) = &param.kind else {
+                    return Err(Diagnostic::error(ErrorCode::TypeMismatch, param.span, "'is' expects a type")
+                        .with_primary_label("not a type"));
+                };
+                let Some(member) = members.iter().find(|member| ***member == *t) else {
+                    return Err(not_a_member(t, param.span));
+                };
+                (Type::optional(member.clone()), Some(member.clone()))
+            }
+            // `then(|T -> R| ..)` runs the closure when the value is a T, with
+            // `@` narrowed to T, and passes the union on unchanged
+            "then" => {
+                let Type::Function { param_type, .. } = param.type_check(env)?.resolve().clone() else {
+                    return Err(Diagnostic::error(ErrorCode::TypeMismatch, param.span, "'then' expects a function")
+                        .with_primary_label("not a function"));
+                };
+                let Some(member) = members.iter().find(|member| ***member != Type::None && **member == param_type) else {
+                    return Err(not_a_member(&param_type, param.span));
+                };
+                (context_type.clone(), Some(member.clone()))
+            }
+            // `?T.map(|T -> R| ..)` is `?R`
+            "map" => {
+                let value_type = optional_value()?;
+                match param.type_check(env)?.resolve() {
+                    Type::Function { param_type, return_type } if param_type.accepts(value_type) => {
+                        (Type::optional(return_type.clone()), Some(value_type.clone()))
+                    }
+                    t => {
+                        return Err(Diagnostic::error(ErrorCode::TypeMismatch, param.span, "Argument of 'map' does not take the optional's value")
+                            .with_primary_label(format!("expected |{} -> _|, found {}", value_type, t)))
+                    }
+                }
+            }
+            // `map_or(default)` is the member of the default's type, or the
+            // default when the value is anything else; either way that
+            // member is handled
+            "map_or" => {
+                let hint = context_type.optional_of().map(Arc::deref);
+                let default_type = param.check_expected(env, hint)?;
+                let Some(member) = members.iter().find(|member| ***member != Type::None && member.accepts(&default_type)) else {
+                    return Err(not_a_member(&default_type, param.span));
+                };
+                (member.deref().clone(), Some(member.clone()))
+            }
+            // `?T.unwrap_or(default)` is T
+            "unwrap_or" => {
+                let value_type = optional_value()?;
+                let default_type = param.check_expected(env, Some(value_type))?;
+                if !value_type.accepts(&default_type) {
+                    return Err(Diagnostic::error(ErrorCode::TypeMismatch, param.span, "Default of 'unwrap_or' does not match the optional's value")
+                        .with_primary_label(format!("expected {}, found {}", value_type, default_type)));
+                }
+                (value_type.deref().clone(), None)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+
+    // the left side of a compound assignment has to be a variable, or a
+    // field of one, marked with `*`, or with `**` when it is shared
+    fn check_mutation(&self, env: &TypeEnv) -> Result<(), Diagnostic> {
+        let (mut target, marker) = match &self.kind {
+            ExprKind::Mut { expr, shared } => (expr.deref(), Some(*shared)),
+            _ => (self, None),
+        };
+        while let ExprKind::FieldAccess { context, .. } = &target.kind {
+            target = context;
+        }
+        let ExprKind::Identifier(name) = &target.kind else {
+            return Err(Diagnostic::error(ErrorCode::InvalidAssignTarget, target.span, "Invalid left-hand side of compound assignment")
+                .with_primary_label("expected a variable"));
+        };
+        // undefined variables are reported when the target is checked
+        let Some((binding, captured)) = env.lookup(name) else {
+            return Ok(());
+        };
+        let shared = binding.type_def.is_shared();
+        match marker {
+            None => {
+                let mark = if shared { "**" } else { "*" };
+                return Err(Diagnostic::error(ErrorCode::InvalidMutation, self.span, format!("Cannot mutate `{}` without marking it", name))
+                    .with_primary_label(format!("this is {}", binding.type_def))
+                    .with_note(format!("write `{}{} op= ..`", mark, name)));
+            }
+            Some(false) if shared => {
+                return Err(Diagnostic::error(ErrorCode::InvalidMutation, self.span, format!("Shared variable `{}` must be mutated with `**`", name))
+                    .with_primary_label(format!("this is {}", binding.type_def))
+                    .with_note(format!("write `**{} op= ..`", name)));
+            }
+            Some(true) if !shared => {
+                return Err(Diagnostic::error(ErrorCode::InvalidMutation, self.span, format!("`{}` is not shared, so it can't be mutated with `**`", name))
+                    .with_primary_label(format!("this is {}", binding.type_def))
+                    .with_note("`**` is for variables declared with `as_shared`; use `*` for this one"));
+            }
+            _ => {}
+        }
+        if captured && !shared {
+            return Err(Diagnostic::error(ErrorCode::MutatedCapture, target.span, format!("Cannot mutate captured variable `{}`", name))
+                .with_primary_label("captured by value from an enclosing scope")
+                .with_note("only shared variables can be mutated from inside a closure"));
         }
+        Ok(())
     }
+
+    // variable at the root of a chain of `then` calls, whose members get
+    // narrowed by the chain
+    fn narrowed_var(&self) -> Option<&String> {
+        match &self.kind {
+            ExprKind::Identifier(name) => Some(name),
+            ExprKind::MethodCall { name, context, .. } if name.as_str() == "then" => context.narrowed_var(),
+            _ => None,
+        }
+    }
+
+    // like check_expected, but leaves Unknown element types in place so
+    // nested arrays such as [[], [1]] can be resolved by their siblings
+    fn array_type(&self, env: &mut TypeEnv, expected: Option<&Type>) -> Result<Type, Diagnostic> {
+        let ExprKind::Array(arr) = &self.kind else {
+            return self.check_expected(env, expected);
+        };
+        let mut element_type: Option<Type> = match expected.map(Type::resolve) {
+            Some(Type::Array { array_type }) => Some(array_type.deref().clone()),
+            _ => None,
+        };
+        let mut first: Option<Span> = None;
+        for expr in arr {
+            let expr_type = expr.array_type(env, element_type.as_ref())?;
+            let unified = match &element_type {
+                None => Some(expr_type.clone()),
+                Some(t) => t.unify(&expr_type),
+            };
+            match unified {
+                Some(t) => element_type = Some(t),
+                None => {
+                    let mut err = Diagnostic::error(ErrorCode::TypeMismatch, expr.span, "Array elements must be of the same type.")
+                        .with_primary_label(format!("expected {}, found {}", element_type.unwrap_or(Type::Unknown), expr_type));
+                    if let Some(first) = first {
+                        err = err.with_label(first, "array element type set here");
+                    }
+                    return Err(err);
+                }
+            }
+            first.get_or_insert(expr.span);
+        }
+        Ok(Type::Array {
+            array_type: Arc::new(element_type.unwrap_or(Type::Unknown)),
+        })
+    }
+
     pub fn primative_str(&self) -> &'static str{
-        match self {
-            Expr::Bool(_) => {
+        match self.kind {
+            ExprKind::Bool(_) => {
                 "bool"
             }
-            Expr::Uint(_) => {
+            ExprKind::Uint(_) => {
                 "uint"
             }
-            Expr::Int(_) => {
+            ExprKind::Int(_) => {
                 "int"
             }
-            Expr::Float(_) => {
+            ExprKind::Float(_) => {
                 "float"
             }
-            Expr::Char(_) => {
+            ExprKind::Char(_) => {
                 "char"
             }
-            Expr::String(_) => {
+            ExprKind::String(_) => {
                 "string"
             }
             _ => {
@@ -250,341 +805,199 @@ impl Expr {
         }
     }
     pub fn is_primative(&self) -> bool {
-        match self {
-            Expr::Bool(_) => {
-                true
-            }
-            Expr::Uint(_) => {
-                true
-            }
-            Expr::Int(_) => {
-                true
-            }
-            Expr::Float(_) => {
-                true
-            }
-            Expr::Char(_) => {
-                true
-            }
-            Expr::String(_) => {
-                true
-            }
-            _ => {
-                false
-            }
-        }
+        matches!(
+            self.kind,
+            ExprKind::Bool(_)
+                | ExprKind::Uint(_)
+                | ExprKind::Int(_)
+                | ExprKind::Float(_)
+                | ExprKind::Char(_)
+                | ExprKind::String(_)
+        )
     }
-    pub fn is_bad_primative(left: Arc<Expr>, op: Arc<Operator>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        if right.is_primative() {
-            return Err(ParseError::BadExpress(format!("Expect {} while reducing expression, found {}",left.primative_str(),right.primative_str())));
-        } else {
-            Ok(Expr::BinaryOp { left, op: Operator::Neq.into(), right})
-        }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    UnexpectedToken(Token, Span, String),
+    BadToken(Token, Span, String),
+    BadExpress(Span, String),
+    UnexpectedEOF(Span),
+}
+
+fn found(token: &Token) -> String {
+    match token {
+        Token::EOF => "found end of file".to_string(),
+        Token::Invalid(c) => format!("found invalid character `{}`", c),
+        token => format!("found `{}`", token),
     }
-    pub fn neq(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError>{
-        match *left{
-            Expr::Bool(ba) => {
-                match *right{
-                    Expr::Bool(bb) => {
-                        Ok(Expr::Bool(ba!=bb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Neq.into(), right)
-                    }
-                }
-            }
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Bool(ua!=ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Neq.into(), right)
-                    }
-                }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Diagnostic {
+        match err {
+            ParseError::UnexpectedToken(token, span, msg) => {
+                Diagnostic::error(ErrorCode::UnexpectedToken, span, msg)
+                    .with_primary_label(found(&token))
             }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Bool(ia!=ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Neq.into(), right)
-                    }
-                }
+            ParseError::BadToken(token, span, msg) => {
+                Diagnostic::error(ErrorCode::BadToken, span, msg)
+                    .with_primary_label(found(&token))
             }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Bool(fa!=fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Neq.into(), right)
-                    }
-                }
+            ParseError::BadExpress(span, msg) => {
+                Diagnostic::error(ErrorCode::BadExpression, span, msg)
             }
-            _ => {
-                if Arc::<Expr>::as_ptr(&left)==Arc::<Expr>::as_ptr(&right) {
-                    Ok(Expr::Bool(false))
-                } else {
-                    Ok(Expr::BinaryOp { left, op: Operator::Neq.into(), right})
-                }
+            ParseError::UnexpectedEOF(span) => {
+                Diagnostic::error(ErrorCode::UnexpectedEOF, span, "Unexpected end of file")
             }
         }
     }
-    pub fn eq(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Bool(ba) => {
-                match *right{
-                    Expr::Bool(bb) => {
-                        Ok(Expr::Bool(ba==bb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Eq.into(), right)
-                    }
-                }
-            }
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Bool(ua==ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Eq.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Bool(ia==ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Eq.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Bool(fa==fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Eq.into(), right)
-                    }
-                }
-            }
-            _ => {
-                if Arc::<Expr>::as_ptr(&left)==Arc::<Expr>::as_ptr(&right) {
-                    Ok(Expr::Bool(true))
-                } else {
-                    Ok(Expr::BinaryOp { left, op: Operator::Eq.into(), right})
-                }
-            }
-        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // type checks source, giving back the program's type or its error, and
+    // any warnings
+    fn check(source: &str) -> (Result<Type, Diagnostic>, Vec<Diagnostic>) {
+        let (ast, diagnostics) = Parser::new(Lexer::new(source)).parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let mut env = TypeEnv::new();
+        let result = ast.type_check(&mut env);
+        (result, env.diagnostics)
     }
-    pub fn modd(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Uint(ua%ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mod.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Int(ia%ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mod.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Float(fa%fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mod.into(), right)
-                    }
-                }
-            }
-            _ => {
-                Ok(Expr::BinaryOp { left, op: Operator::Mod.into(), right})
-            }
+
+    fn type_of(source: &str) -> String {
+        match check(source).0 {
+            Ok(t) => t.to_string(),
+            Err(diag) => panic!("{}", diag.render(source, "test")),
         }
     }
-    pub fn div(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Uint(ua/ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Div.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Int(ia/ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Div.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Float(fa/fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Div.into(), right)
-                    }
-                }
-            }
-            _ => {
-                Ok(Expr::BinaryOp { left, op: Operator::Div.into(), right})
-            }
+
+    fn error_code(source: &str) -> ErrorCode {
+        match check(source).0 {
+            Ok(t) => panic!("expected an error, found {}", t),
+            Err(diag) => diag.code,
         }
     }
-    pub fn mult(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Uint(ua*ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mul.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Int(ia*ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mul.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Float(fa*fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Mul.into(), right)
-                    }
-                }
-            }
-            _ => {
-                Ok(Expr::BinaryOp { left, op: Operator::Mul.into(), right})
-            }
-        }
+
+    #[test]
+    fn unions_accept_plain_member_values() {
+        assert_eq!(type_of("|int -> <int | ()>| { @ }(1);"), "?int");
+        assert_eq!(type_of("|<int | String> -> int| { 0 }(\"s\");"), "int");
+        assert_eq!(type_of("
+            |{a: int, b: int,} -> <int, ()>| {
+                if @.a == @.b {
+                    return <()>;
+                };
+                if @.a > @.b { @.a } else { @.b }
+            }.as(max_or_none);
+        "), "|{ a: int, b: int } -> ?int|");
+        assert_eq!(error_code("|int -> <int | ()>| { \"s\" }(1);"), ErrorCode::ReturnTypeMismatch);
+        assert_eq!(error_code("|int -> int| { @ }(<1>);"), ErrorCode::TypeMismatch);
     }
-    pub fn sub(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Uint(ua-ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Sub.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Int(ia-ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Sub.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Float(fa-fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Sub.into(), right)
-                    }
-                }
-            }
-            _ => {
-                Ok(Expr::BinaryOp { left, op: Operator::Sub.into(), right})
-            }
-        }
+
+    #[test]
+    fn calls_check_their_argument() {
+        assert_eq!(type_of("|{a: int, b: int,} -> int| { @.a + @.b }.as(add); add({a: 1, b: 2,});"), "int");
+        assert_eq!(type_of("|[int] -> bool| { true }.as(f); f([1, 2, 3]);"), "bool");
+        // an empty array takes its type from the parameter
+        assert_eq!(type_of("|[int] -> bool| { true }([]);"), "bool");
+        assert_eq!(error_code("|int -> int| { @ }.as(f); f(\"s\");"), ErrorCode::TypeMismatch);
+        assert_eq!(error_code("|{a: int,} -> int| { @.a }({b: 1,});"), ErrorCode::TypeMismatch);
+        assert_eq!(error_code("1.as(x); x(2);"), ErrorCode::NotCallable);
     }
-    pub fn add(left: Arc<Expr>, right: Arc<Expr>) -> Result<Expr, ParseError> {
-        match *left{
-            Expr::Uint(ua) => {
-                match *right{
-                    Expr::Uint(ub) => {
-                        Ok(Expr::Uint(ua+ub))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Add.into(), right)
-                    }
-                }
-            }
-            Expr::Int(ia) => {
-                match *right{
-                    Expr::Int(ib) => {
-                        Ok(Expr::Int(ia+ib))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Add.into(), right)
-                    }
-                }
-            }
-            Expr::Float(fa) => {
-                match *right{
-                    Expr::Float(fb) => {
-                        Ok(Expr::Float(fa+fb))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Add.into(), right)
-                    }
-                }
-            }
-            Expr::String(ref sa) => {
-                match *right{
-                    Expr::String(ref sb) => {
-                        Ok(Expr::String(format!("{}{}",*sa,*sb).into()))
-                    }
-                    _ => {
-                        Expr::is_bad_primative(left, Operator::Add.into(), right)
-                    }
-                }
-            }
-            _ => {
-                Ok(Expr::BinaryOp { left, op: Operator::Add.into(), right})
-            }
-        }
+
+    #[test]
+    fn field_access_resolves_struct_fields() {
+        assert_eq!(type_of("{a: 1, b: \"s\",}.b;"), "String");
+        assert_eq!(type_of("(1, 'c').1;"), "char");
+        assert_eq!(type_of("Point: {x: int, y: float,}; |Point -> float| { @.y }({x: 1, y: 2.0,});"), "float");
+        assert_eq!(type_of("{a: {b: [true],},}.a.b;"), "[bool]");
+        let (result, _) = check("{a: 1, b: 2,}.c;");
+        let diag = result.unwrap_err();
+        assert_eq!(diag.code, ErrorCode::UnknownField);
+        assert_eq!(diag.notes, vec!["available fields are: `a`, `b`".to_string()]);
+        assert_eq!(error_code("1.a;"), ErrorCode::UnknownField);
     }
-}
 
-#[derive(Debug)]
-pub enum ParseError {
-    UnexpectedToken(Token, String),
-    BadToken(Token, String),
-    BadExpress(String),
-    UnexpectedEOF,
-}
+    #[test]
+    fn returns_match_the_declared_return_type() {
+        assert_eq!(type_of("|int -> int| { if @ > 0 { return 1; }; 2 };"), "|int -> int|");
+        assert_eq!(type_of("|int -> ?int| { if @ > 0 { return <()>; }; <@> };"), "|int -> ?int|");
+        assert_eq!(error_code("|int -> int| { if @ > 0 { return \"s\"; }; 2 };"), ErrorCode::ReturnTypeMismatch);
+        assert_eq!(error_code("|int -> int| { \"s\" };"), ErrorCode::ReturnTypeMismatch);
+        assert_eq!(error_code("return 1;"), ErrorCode::ReturnOutsideFunction);
+    }
+
+    #[test]
+    fn param_has_the_function_param_type() {
+        assert_eq!(type_of("|{a: int,} -> int| { @.a };"), "|{ a: int } -> int|");
+        assert_eq!(error_code("|int -> String| { @ };"), ErrorCode::ReturnTypeMismatch);
+        // an inner function's `@` shadows the outer one
+        assert_eq!(type_of("|int -> |String -> String|| { |String -> String| { @ } };"), "|int -> |String -> String||");
+        assert_eq!(error_code("|int -> |String -> int|| { |String -> int| { @ } };"), ErrorCode::ReturnTypeMismatch);
+        assert_eq!(error_code("@;"), ErrorCode::InvalidParam);
+    }
+
+    #[test]
+    fn closures_see_outer_bindings() {
+        assert_eq!(type_of("1.as(x); |int -> int| { x + @ }(2);"), "int");
+        assert_eq!(type_of("0.as_shared(n); |int -> int| { **n += @; n };"), "|int -> int|");
+        assert_eq!(error_code("*0.as(n); |int -> int| { *n += @; n };"), ErrorCode::MutatedCapture);
+        assert_eq!(error_code("|int -> int| { y };"), ErrorCode::UndefinedIdentifier);
+        // bindings made inside a function don't leak out of it
+        assert_eq!(error_code("|int -> int| { @.as(z); z }(1); z;"), ErrorCode::UndefinedIdentifier);
+    }
 
+    #[test]
+    fn uints_reject_negative_values() {
+        assert_eq!(error_code("-1u;"), ErrorCode::NegativeUnsigned);
+        assert_eq!(error_code("1u.as(n); -n;"), ErrorCode::NegativeUnsigned);
+        assert_eq!(type_of("1u.wrapping_sub(2u);"), "uint");
+        assert_eq!(type_of("1u.saturating_sub(2u);"), "uint");
+        assert_eq!(type_of("1.checked_add(2);"), "?int");
+    }
+
+    #[test]
+    fn union_methods_narrow_to_one_member() {
+        let union = "|int -> <int | bool>| { <@> }.as(f); 2.pass_to(f).as(a);";
+        assert_eq!(type_of(&format!("{union} a.is(int);")), "?int");
+        assert_eq!(type_of(&format!("{union} a.is(int).unwrap_or(-1);")), "int");
+        assert_eq!(type_of(&format!("{union} a.is(int).map(|int -> bool| {{ @ > 5 }});")), "?bool");
+        assert_eq!(type_of(&format!("{union} a.map_or(false);")), "bool");
+        assert_eq!(type_of(&format!("{union} a.then(|int -> int| {{ @ }});")), "<int | bool>");
+        assert_eq!(error_code(&format!("{union} a.is(float);")), ErrorCode::TypeMismatch);
+        assert_eq!(error_code(&format!("{union} a.map(|int -> int| {{ @ }});")), ErrorCode::TypeMismatch);
+    }
+
+    #[test]
+    fn unions_warn_about_members_they_never_handle() {
+        let union = "|int -> <int | bool>| { <@> }.as(f); 2.pass_to(f).as(a);";
+        let warnings = |source: String| {
+            let (result, diagnostics) = check(&source);
+            result.unwrap();
+            diagnostics.iter().map(|d| (d.code, d.is_error())).collect::<Vec<_>>()
+        };
+        assert_eq!(warnings(format!("{union} a.is(int).map_or(0);")), [(ErrorCode::UnhandledMember, false)]);
+        assert_eq!(warnings(format!("{union} a.is(int); a.is(bool);")), []);
+        assert_eq!(warnings(format!("{union} a.then(|int -> int| {{ @ }}).map_or(false);")), []);
+        // never taken apart at all, so nothing is left unhandled
+        assert_eq!(warnings(union.to_string()), []);
+    }
+
+    #[test]
+    fn mutation_needs_the_matching_marker() {
+        assert_eq!(error_code("0.as(var_a); var_a += 1;"), ErrorCode::InvalidMutation);
+        assert_eq!(error_code("*0.as(var_a); **var_a += 1;"), ErrorCode::InvalidMutation);
+        assert_eq!(error_code("0.as_shared(var_a); *var_a += 1;"), ErrorCode::InvalidMutation);
+        assert_eq!(error_code("0.as_shared(var_a); var_a += 1;"), ErrorCode::InvalidMutation);
+        assert_eq!(error_code("**0.as(var_a);"), ErrorCode::InvalidMutation);
+        assert_eq!(type_of("*0.as(var_a); *var_a += 1; var_a;"), "int");
+        assert_eq!(type_of("0.as_shared(var_a); **var_a += 1; var_a;"), "int");
+        assert_eq!(type_of("*{x: 1,}.as(p); *p.x += 1; p.x;"), "int");
+    }
+}
@@ -1,6 +1,7 @@
-use std::{str::Chars, sync::Arc};
+use std::{fmt, str::Chars, sync::Arc};
 
-use crate::type_def::Type;
+use crate::bignum::BigInt;
+use crate::span::{Span, Spanned};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Operator {
@@ -24,6 +25,7 @@ pub enum Operator {
     BitOr,
 }
 #[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Generic,
     Option,
@@ -37,22 +39,27 @@ pub enum Token {
     TString,
     FnTypes,
     Bool(bool),
-    Int(i64),
-    Uint(u64),
+    Int(BigInt),
+    Uint(BigInt),
     Char(char),
     Float(f64),
     String(Arc<String>),
     Identifier(Arc<String>),
     Assign,
     ImplAssign,
+    AssignShared,
     PassTo,
     If,
     Else,
+    Return,
+    Ghost,
     Operator(Arc<Operator>),
     Arrow,
     Comma,
     Period,
+    TupleIndex(usize),
     Colon,
+    PathSep,
     Semicolon,
     LeftParen,
     RightParen,
@@ -62,15 +69,96 @@ pub enum Token {
     RightBracket,
     EOF,
     Param,
-    Mut,
-    SharedMut,
     Macro,
     Invalid(char),
 }
 
+// operators and tokens print as they are written in source, for diagnostics
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::AssignOp(op) => return write!(f, "{}=", op),
+            Operator::Sub => "-",
+            Operator::Add => "+",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+            Operator::Not => "!",
+            Operator::Eq => "==",
+            Operator::Neq => "!=",
+            Operator::Gt => ">",
+            Operator::Lt => "<",
+            Operator::GtEq => ">=",
+            Operator::LtEq => "<=",
+            Operator::BitXor => "^",
+            Operator::And => "&&",
+            Operator::BitAnd => "&",
+            Operator::Or => "||",
+            Operator::BitOr => "|||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Bool(b) => return write!(f, "{}", b),
+            Token::Int(i) => return write!(f, "{}", i),
+            Token::Uint(u) => return write!(f, "{}u", u),
+            Token::Char(c) => return write!(f, "'{}'", c),
+            Token::Float(x) => return write!(f, "{}", x),
+            Token::String(s) => return write!(f, "\"{}\"", s),
+            Token::Identifier(name) => return write!(f, "{}", name),
+            Token::Operator(op) => return write!(f, "{}", op),
+            Token::TupleIndex(i) => return write!(f, ".{}", i),
+            Token::Invalid(c) => return write!(f, "{}", c),
+            Token::Generic => "Self",
+            Token::Option => "?",
+            Token::TNone => "None",
+            Token::TSome => "Some",
+            Token::TBool => "bool",
+            Token::TInt => "int",
+            Token::TUint => "uint",
+            Token::TChar => "char",
+            Token::TFloat => "float",
+            Token::TString => "String",
+            Token::FnTypes => "|",
+            Token::Assign => "as",
+            Token::ImplAssign => "impl_as",
+            Token::AssignShared => "as_shared",
+            Token::PassTo => "pass_to",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::Return => "return",
+            Token::Ghost => "ghost",
+            Token::Arrow => "->",
+            Token::Comma => ",",
+            Token::Period => ".",
+            Token::Colon => ":",
+            Token::PathSep => "::",
+            Token::Semicolon => ";",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "[",
+            Token::RightBrace => "]",
+            Token::LeftBracket => "{",
+            Token::RightBracket => "}",
+            Token::EOF => "end of file",
+            Token::Param => "@",
+            Token::Macro => "#",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Clone)]
 pub struct Lexer<'a> {
     input: Chars<'a>,
     current_char: Option<char>,
+    pos: u32,
+    line: u32,
+    col: u32,
 }
 
 impl<'a> Lexer<'a> {
@@ -78,12 +166,24 @@ impl<'a> Lexer<'a> {
         let mut lexer = Lexer {
             input: input.chars(),
             current_char: None,
+            pos: 0,
+            line: 1,
+            col: 1,
         };
         lexer.advance();
         lexer
     }
 
     fn advance(&mut self) {
+        if let Some(c) = self.current_char {
+            self.pos += c.len_utf8() as u32;
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
         self.current_char = self.input.next();
     }
 
@@ -91,15 +191,18 @@ impl<'a> Lexer<'a> {
         self.input.clone().next()
     }
 
-    pub fn next_token(&mut self) -> Token {
-        while let Some(c) = self.current_char {
+    pub fn next_token(&mut self) -> Spanned<Token> {
+        while self.current_char.is_some_and(|c| c==' ' || c=='\t'|| c=='\n'|| c=='\r'){
+            self.advance();
+        }
+        let (start, line, col) = (self.pos, self.line, self.col);
+        let token = self.scan_token();
+        Spanned::new(token, Span::new(start, self.pos, line, col))
+    }
+
+    fn scan_token(&mut self) -> Token {
+        if let Some(c) = self.current_char {
             match c {
-                ' ' | '\t' | '\n' | '\r' => {
-                    while self.current_char.is_some_and(|c| c==' ' || c=='\t'|| c=='\n'|| c=='\r'){
-                        self.advance();
-                    }
-                    continue;
-                }
                 '>' => {
                     self.advance();
                     if self.current_char.is_some_and(|c|c=='='){
@@ -125,16 +228,33 @@ impl<'a> Lexer<'a> {
                 '[' => return self.consume(Token::LeftBrace),
                 ']' => return self.consume(Token::RightBrace),
                 ',' => return self.consume(Token::Comma),
-                '.' => return self.consume(Token::Period),
-                ':' => return self.consume(Token::Colon),
+                '.' => {
+                    if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        return self.tuple_index();
+                    }
+                    return self.consume(Token::Period);
+                }
+                ':' => {
+                    if self.peek() == Some(':') {
+                        self.advance();
+                        self.advance();
+                        return Token::PathSep;
+                    } else {
+                        return self.consume(Token::Colon);
+                    }
+                }
                 ';' => return self.consume(Token::Semicolon),
                 '-' => {
                     if self.peek() == Some('>') {
                         self.advance();
                         self.advance();
                         return Token::Arrow;
+                    } else if self.peek() == Some('=') {
+                        self.advance();
+                        self.advance();
+                        return Token::Operator(Operator::AssignOp(Operator::Sub.into()).into());
                     } else {
-                        return self.consume(Token::Operator(Operator::Sub.into()).into());
+                        return self.consume(Token::Operator(Operator::Sub.into()));
                     }
                 }
                 '@' => {
@@ -154,15 +274,6 @@ impl<'a> Lexer<'a> {
                         return Token::Operator(Operator::Add.into());
                     }
                 }
-                '~' => {
-                    self.advance();
-                    if self.current_char.is_some_and(|c|c=='~'){
-                        self.advance();
-                        return Token::SharedMut;
-                    } else {
-                        return Token::Mut;
-                    }
-                }
                 '*' => {
                     self.advance();
                     if self.current_char.is_some_and(|c|c=='='){
@@ -259,16 +370,19 @@ impl<'a> Lexer<'a> {
         let mut is_unsigned = false;
         let mut is_float = false;
         while let Some(c) = self.current_char {
-            if c=='u' && !num_str.chars().last().is_some_and(|c| c=='.') {
+            if c=='u' && !num_str.ends_with('.') && !is_float {
                 is_unsigned = true;
+                self.advance();
                 break;
             } else {
-                if c.is_numeric() {
+                if c.is_ascii_digit() {
                     num_str.push(c);
                     self.advance();
                 } else if c == '.' && !is_float{
-                    if self.peek().is_some_and(|c| c.is_digit(10)){
+                    if self.peek().is_some_and(|c| c.is_ascii_digit()){
                         is_float = true;
+                        num_str.push(c);
+                        self.advance();
                     } else {
                         break;
                     }
@@ -283,18 +397,33 @@ impl<'a> Lexer<'a> {
                 Err(_) => Token::Invalid('.'),
             }
         } else if is_unsigned {
-            match num_str.parse::<u64>() {
-                Ok(n) => Token::Uint(n),
-                Err(_) => Token::Invalid(' '),
+            match BigInt::parse(&num_str) {
+                Some(n) => Token::Uint(n),
+                None => Token::Invalid(' '),
             }
         } else {
-            match num_str.parse::<i64>() {
-                Ok(n) => Token::Int(n),
-                Err(_) => Token::Invalid(' '),
+            match BigInt::parse(&num_str) {
+                Some(n) => Token::Int(n),
+                None => Token::Invalid(' '),
             }
         }
     }
 
+    // `.0`, `.1`, ... are lexed as one token so that `t.0.1` doesn't turn
+    // into a float
+    fn tuple_index(&mut self) -> Token {
+        self.advance();
+        let mut index = String::new();
+        while let Some(c) = self.current_char.filter(|c| c.is_ascii_digit()) {
+            index.push(c);
+            self.advance();
+        }
+        match index.parse::<usize>() {
+            Ok(i) => Token::TupleIndex(i),
+            Err(_) => Token::Invalid('.'),
+        }
+    }
+
     fn char_literal(&mut self) -> Token {
         self.advance();
         if let Some(c) = self.current_char {
@@ -344,10 +473,13 @@ impl<'a> Lexer<'a> {
             "String" => Token::TString,
             "if" => Token::If,
             "else" => Token::Else,
+            "return" => Token::Return,
+            "ghost" => Token::Ghost,
             "true" => Token::Bool(true),
             "false" => Token::Bool(false),
             "as" => Token::Assign,
             "impl_as" => Token::ImplAssign,
+            "as_shared" => Token::AssignShared,
             "pass_to" => Token::PassTo,
             _ => Token::Identifier(Arc::new(ident)),
         }
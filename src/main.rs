@@ -4,78 +4,24 @@ mod expr;
 mod type_def;
 mod engine;
 mod static_analyzer;
+mod span;
+mod diagnostic;
+mod optimizer;
+mod bignum;
+mod scheduler;
 
-use std::collections::HashMap;
+use std::{env, fs, process};
 
+use diagnostic::Diagnostic;
 use engine::Interpreter;
+use expr::TypeEnv;
+use optimizer::ConstantFolder;
+use scheduler::ThreadPool;
 
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 
 fn main() {
-    let input = r#"
-        TypeA: int;
-        TypeB: TypeA;
-        TypeC: TypeB;
-        TypeD: TypeA;
-        TypeE: TypeD;
-        |TypeE -> uint| {
-            0.as(test);
-            '1'.as(var_b);
-
-
-            a + b + c;
-            a + (b + c);
-            (!a + !(b + c)).as(will_this_work);
-        }.test(None)
-         .as(test)
-         .as_shared(asdf);
-    "#;
-    let input = r#"
-        TypeTest: {
-            a: Self,
-            b: float,
-        };
-        TypeB: {
-            a: int,
-            b: float,
-        };
-
-
-        |Self -> Self| {
-            5
-        }.asdsf()
-         .test() 
-         .lkjh();
-    "#;
-    let input = r#"
-        test: {
-            a: ?int,
-            b: int,
-        };
-
-        |int->int| {
-            @
-        };
-        5.as(test1);
-        2.as(a);
-
-        if a==2 {
-            Some(3)
-        } else {
-            None
-        }.as(a).test(a);
-        {
-            a: 2,
-            b: 3,
-        };
-
-        test2
-            .pass_to(4)
-            .as(test2);
-
-        test2==test1;
-    "#;
     let input = r#"
         
 
@@ -85,20 +31,65 @@ fn main() {
 
     2.pass_to(test);
     "#;
-    let lexer = Lexer::new(input);
-    let mut parser = Parser::new(lexer);
-    //let mut interpreter = Interpreter::new();
-    //interpreter.interpret(&mut parser).expect("Interpretation failed");
-    match parser.parse() {
-        Ok(ast) =>  {
-        println!("{:#?}", ast);
-            let mut env = HashMap::new();
-            let t = ast.type_check(&mut env);
-            match t {
-                Ok(t) => println!("{:#?}", t),
-                Err(e) => println!("Error: {:?}", e),
+    let args: Vec<String> = env::args().skip(1).collect();
+    let fold = !args.iter().any(|a| a == "--no-fold");
+    let stats = args.iter().any(|a| a == "--stats");
+    let sequential = args.iter().any(|a| a == "--sequential");
+    let (file_name, source) = match args.iter().find(|a| !a.starts_with("--")) {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(source) => (path.clone(), source),
+            Err(e) => {
+                eprintln!("error: could not read {}: {}", path, e);
+                process::exit(1);
             }
         },
-        Err(e) => println!("Error: {:?}", e),
+        None => ("<demo>".to_string(), input.to_string()),
+    };
+    let report = |diag: Diagnostic| -> ! {
+        eprint!("{}", diag.render(&source, &file_name));
+        process::exit(1);
+    };
+
+    let lexer = Lexer::new(&source);
+    let mut parser = Parser::new(lexer);
+    let (ast, diagnostics) = parser.parse();
+    for diag in &diagnostics {
+        eprint!("{}", diag.render(&source, &file_name));
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        process::exit(1);
+    }
+    println!("{:#?}", ast);
+    let mut env = TypeEnv::new();
+    let t = ast.type_check(&mut env).unwrap_or_else(|e| report(e));
+    for diag in &env.diagnostics {
+        eprint!("{}", diag.render(&source, &file_name));
+    }
+    println!("{}", t);
+    let ast = if fold {
+        let mut folder = ConstantFolder::new();
+        let folded = folder.fold(&ast.into());
+        for diag in &folder.diagnostics {
+            eprint!("{}", diag.render(&source, &file_name));
+        }
+        if folder.diagnostics.iter().any(|d| d.is_error()) {
+            process::exit(1);
+        }
+        folded
+    } else {
+        ast.into()
+    };
+    // --sequential keeps every call on this thread, in program order
+    let mut interpreter = if sequential {
+        Interpreter::new()
+    } else {
+        Interpreter::with_pool(ThreadPool::with_available_parallelism())
+    };
+    match interpreter.interpret(&ast) {
+        Ok(v) => println!("{}", v),
+        Err(e) => report(e),
+    }
+    if stats {
+        eprintln!("copied on write: {}", interpreter.copy_counts());
     }
 }
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::engine::{compare, integer_op};
+use crate::expr::{Expr, ExprKind};
+use crate::lexer::Operator;
+use crate::span::Span;
+
+pub struct ConstantFolder {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+enum Folded {
+    Value(ExprKind),
+    Keep,
+}
+
+impl ConstantFolder {
+    pub fn new() -> ConstantFolder {
+        ConstantFolder { diagnostics: Vec::new() }
+    }
+
+    pub fn fold(&mut self, expr: &Arc<Expr>) -> Arc<Expr> {
+        let kind = match &expr.kind {
+            ExprKind::Option(Some(inner)) => ExprKind::Option(Some(self.fold(inner))),
+            ExprKind::Array(exprs) => ExprKind::Array(exprs.iter().map(|e| self.fold(e)).collect()),
+            ExprKind::Struct { pairs } => {
+                let pairs: HashMap<String, Arc<Expr>> = pairs
+                    .iter()
+                    .map(|(k, v)| (k.clone(), self.fold(v)))
+                    .collect();
+                ExprKind::Struct { pairs }
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                let left = self.fold(left);
+                // the right side of a short circuit never runs, so don't report errors in it
+                match (&**op, &left.kind) {
+                    (Operator::And, ExprKind::Bool(false)) | (Operator::Or, ExprKind::Bool(true)) => {
+                        return Expr::new(left.kind.clone(), expr.span).into();
+                    }
+                    _ => {}
+                }
+                let right = self.fold(right);
+                match self.fold_binary(op, &left, &right, expr.span) {
+                    Folded::Value(kind) => kind,
+                    Folded::Keep => ExprKind::BinaryOp { left, op: op.clone(), right },
+                }
+            }
+            ExprKind::UnaryOp { op, expr: inner } => {
+                let inner = self.fold(inner);
+                match self.fold_unary(op, &inner) {
+                    Folded::Value(kind) => kind,
+                    Folded::Keep => ExprKind::UnaryOp { op: op.clone(), expr: inner },
+                }
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                let condition = self.fold(condition);
+                match condition.kind {
+                    ExprKind::Bool(true) => return self.fold(then_branch),
+                    ExprKind::Bool(false) => return self.fold(else_branch),
+                    _ => ExprKind::If {
+                        condition,
+                        then_branch: self.fold(then_branch),
+                        else_branch: self.fold(else_branch),
+                    },
+                }
+            }
+            ExprKind::Function { param_sig, return_sig, block } => ExprKind::Function {
+                param_sig: param_sig.clone(),
+                return_sig: return_sig.clone(),
+                block: self.fold(block),
+            },
+            ExprKind::Block(exprs) => ExprKind::Block(exprs.iter().map(|e| self.fold(e)).collect()),
+            ExprKind::MethodCall { name, context, param, type_def } => ExprKind::MethodCall {
+                name: name.clone(),
+                context: self.fold(context),
+                param: self.fold(param),
+                type_def: type_def.clone(),
+            },
+            ExprKind::AssignShared { context, param } => ExprKind::AssignShared {
+                context: self.fold(context),
+                param: param.clone(),
+            },
+            ExprKind::Assign { context, param, type_def } => ExprKind::Assign {
+                context: self.fold(context),
+                param: param.clone(),
+                type_def: type_def.clone(),
+            },
+            ExprKind::PassTo { context, param, type_def } => ExprKind::PassTo {
+                context: self.fold(context),
+                param: self.fold(param),
+                type_def: type_def.clone(),
+            },
+            ExprKind::Call { callee, arg } => ExprKind::Call {
+                callee: self.fold(callee),
+                arg: self.fold(arg),
+            },
+            ExprKind::FieldAccess { context, field } => ExprKind::FieldAccess {
+                context: self.fold(context),
+                field: field.clone(),
+            },
+            ExprKind::Return(inner) => ExprKind::Return(self.fold(inner)),
+            ExprKind::Union(inner) => ExprKind::Union(self.fold(inner)),
+            ExprKind::Mut { expr: inner, shared } => ExprKind::Mut {
+                expr: self.fold(inner),
+                shared: *shared,
+            },
+            ExprKind::Convert { target, expr } => ExprKind::Convert {
+                target: target.clone(),
+                expr: self.fold(expr),
+            },
+            _ => return expr.clone(),
+        };
+        Expr::new(kind, expr.span).into()
+    }
+
+    fn overflow(&mut self, span: Span, op: &Operator) -> Folded {
+        self.diagnostics.push(
+            Diagnostic::error(ErrorCode::ArithmeticOverflow, span, format!("this {} operation will overflow", op))
+                .with_primary_label("the result is below zero, which a uint can't hold"),
+        );
+        Folded::Keep
+    }
+
+    fn divide_by_zero(&mut self, span: Span) -> Folded {
+        self.diagnostics.push(
+            Diagnostic::error(ErrorCode::DivisionByZero, span, "this operation will divide by zero")
+                .with_primary_label("attempt to divide by zero"),
+        );
+        Folded::Keep
+    }
+
+    fn fold_unary(&mut self, op: &Operator, expr: &Expr) -> Folded {
+        match (op, &expr.kind) {
+            (Operator::Not, ExprKind::Bool(b)) => Folded::Value(ExprKind::Bool(!b)),
+            (Operator::Sub, ExprKind::Int(i)) => Folded::Value(ExprKind::Int(-i)),
+            (Operator::Sub, ExprKind::Float(f)) => Folded::Value(ExprKind::Float(-f)),
+            _ => Folded::Keep,
+        }
+    }
+
+    fn fold_binary(&mut self, op: &Operator, left: &Expr, right: &Expr, span: Span) -> Folded {
+        use ExprKind::*;
+
+        if !left.is_primative() || !right.is_primative() {
+            return Folded::Keep;
+        }
+
+        match (&left.kind, &right.kind) {
+            (Int(a), Int(b)) => match integer_op(op, a, b) {
+                Some(Some(i)) => Folded::Value(Int(i)),
+                Some(None) => self.divide_by_zero(span),
+                None => fold_comparison(op, a, b),
+            },
+            (Uint(a), Uint(b)) => match integer_op(op, a, b) {
+                Some(Some(u)) if u.is_negative() => self.overflow(span, op),
+                Some(Some(u)) => Folded::Value(Uint(u)),
+                Some(None) => self.divide_by_zero(span),
+                None => fold_comparison(op, a, b),
+            },
+            (Float(a), Float(b)) => {
+                let (a, b) = (*a, *b);
+                match op {
+                    Operator::Add => Folded::Value(Float(a + b)),
+                    Operator::Sub => Folded::Value(Float(a - b)),
+                    Operator::Mul => Folded::Value(Float(a * b)),
+                    Operator::Div | Operator::Mod if b == 0.0 => self.divide_by_zero(span),
+                    Operator::Div => Folded::Value(Float(a / b)),
+                    Operator::Mod => Folded::Value(Float(a % b)),
+                    _ => fold_comparison(op, &a, &b),
+                }
+            }
+            (Bool(a), Bool(b)) => match op {
+                Operator::And | Operator::BitAnd => Folded::Value(Bool(*a && *b)),
+                Operator::Or | Operator::BitOr => Folded::Value(Bool(*a || *b)),
+                Operator::BitXor => Folded::Value(Bool(a ^ b)),
+                Operator::Eq | Operator::Neq => fold_comparison(op, a, b),
+                _ => Folded::Keep,
+            },
+            (Char(a), Char(b)) => fold_comparison(op, a, b),
+            (String(a), String(b)) => match op {
+                Operator::Add => Folded::Value(String(format!("{}{}", a, b).into())),
+                _ => fold_comparison(op, a, b),
+            },
+            _ => Folded::Keep,
+        }
+    }
+}
+
+fn fold_comparison<T: PartialOrd>(op: &Operator, a: &T, b: &T) -> Folded {
+    match compare(op, a, b) {
+        Some(result) => Folded::Value(ExprKind::Bool(result)),
+        None => Folded::Keep,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // folds a single statement, giving back what it folded to and the codes
+    // of any diagnostics
+    fn fold(source: &str) -> (ExprKind, Vec<ErrorCode>) {
+        let (ast, diagnostics) = Parser::new(Lexer::new(source)).parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let mut folder = ConstantFolder::new();
+        let folded = folder.fold(&ast.into());
+        let ExprKind::Block(exprs) = &folded.kind else {
+            panic!("expected a block, found {:?}", folded);
+        };
+        let codes = folder.diagnostics.iter().map(|d| d.code).collect();
+        (exprs[0].kind.clone(), codes)
+    }
+
+    #[test]
+    fn division_by_zero_is_reported() {
+        assert_eq!(fold("1 / 0;").1, vec![ErrorCode::DivisionByZero]);
+        assert_eq!(fold("7 % (2 - 2);").1, vec![ErrorCode::DivisionByZero]);
+        assert_eq!(fold("1.5 / 0.0;").1, vec![ErrorCode::DivisionByZero]);
+    }
+
+    #[test]
+    fn short_circuits_skip_the_right_side() {
+        assert_eq!(fold("true || 1 / 0 == 0;"), (ExprKind::Bool(true), Vec::new()));
+        assert_eq!(fold("false && 1 / 0 == 0;"), (ExprKind::Bool(false), Vec::new()));
+        assert_eq!(fold("false || 1 / 0 == 0;").1, vec![ErrorCode::DivisionByZero]);
+    }
+
+    #[test]
+    fn untaken_branches_are_dropped_unchecked() {
+        let (kind, codes) = fold("if true { 1 } else { 1 / 0 };");
+        assert!(matches!(kind, ExprKind::Block(ref exprs) if exprs[0].kind == ExprKind::Int(1i64.into())), "{:?}", kind);
+        assert_eq!(codes, Vec::new());
+        assert_eq!(fold("if false { 1 / 0 } else { 2 };").1, Vec::new());
+    }
+
+    #[test]
+    fn uint_underflow_is_reported() {
+        assert_eq!(fold("1u - 2u;").1, vec![ErrorCode::ArithmeticOverflow]);
+        assert_eq!(fold("2u - 1u;"), (ExprKind::Uint(1i64.into()), Vec::new()));
+    }
+
+    #[test]
+    fn every_operator_folds() {
+        assert_eq!(fold("2 * 3 + 4 == 10;").0, ExprKind::Bool(true));
+        assert_eq!(fold("(6 & 3) ||| 8 ^ 1;").0, ExprKind::Int(11i64.into()));
+        assert_eq!(fold("'a' < 'b' && 1.5 >= 1.5;").0, ExprKind::Bool(true));
+        assert_eq!(fold("\"a\" + \"b\" != \"ab\";").0, ExprKind::Bool(false));
+        assert_eq!(fold("-(7 / 2) % 2;").0, ExprKind::Int((-1i64).into()));
+    }
+}
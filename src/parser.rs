@@ -1,37 +1,95 @@
 use std::collections::{HashMap, HashSet};
-use std::ops::Deref;
+use std::collections::hash_map::Entry;
 use std::sync::Arc;
 
-use crate::{expr, type_def};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::span::Span;
 use crate::type_def::Type;
 
 use super::lexer::{Token, Operator};
 use super::Lexer;
-use super::expr::{Expr, ParseError};
+use super::expr::{Expr, ExprKind, ParseError};
 
 pub struct Parser<'a> {
     pub lexer: Lexer<'a>,
     current_token: Token,
+    current_span: Span,
+    prev_span: Span,
+    diagnostics: Vec<Diagnostic>,
+    // how many `{` have been consumed without their `}`, for recovery
+    depth: usize,
 }
 
+// Binding powers for infix operators, lowest to highest precedence:
+//
+//   level | operators                  | assoc
+//   ------+----------------------------+------
+//     1   | += -= *= /= %=             | right
+//     2   | ||                         | left
+//     3   | &&                         | left
+//     4   | == !=                      | left
+//     5   | < > <= >=                  | left
+//     6   | |||                        | left
+//     7   | ^                          | left
+//     8   | &                          | left
+//     9   | + -                        | left
+//    10   | * / %                      | left
+//    11   | prefix ! -                 |
+//    12   | postfix calls and .fields  |
+//
+// A left-associative level n binds (2n, 2n+1) so the right operand must be
+// strictly tighter; right-associative levels flip that to (2n+1, 2n).
+fn infix_binding_power(op: &Operator) -> Option<(u8, u8)> {
+    let bp = match op {
+        Operator::AssignOp(_) => (3, 2),
+        Operator::Or => (4, 5),
+        Operator::And => (6, 7),
+        Operator::Eq | Operator::Neq => (8, 9),
+        Operator::Lt | Operator::Gt | Operator::LtEq | Operator::GtEq => (10, 11),
+        Operator::BitOr => (12, 13),
+        Operator::BitXor => (14, 15),
+        Operator::BitAnd => (16, 17),
+        Operator::Add | Operator::Sub => (18, 19),
+        Operator::Mul | Operator::Div | Operator::Mod => (20, 21),
+        Operator::Not => return None,
+    };
+    Some(bp)
+}
+
+const PREFIX_BINDING_POWER: u8 = 22;
+
+// the value of a union literal `<expr>` binds tighter than comparisons, so
+// the closing `>` isn't read as an operator
+const UNION_BINDING_POWER: u8 = 12;
+
 pub struct TypeMap {
     pub name_map: HashMap<String, Arc<Type>>,
-    pub sig_map: HashMap<u64, Arc<Type>>
+    // one shared Arc per distinct type; lookups go through Type's structural
+    // Hash and Eq, so two types only share an entry when they really are equal
+    pub interned: HashSet<Arc<Type>>,
 }
 impl TypeMap {
     pub fn new() -> TypeMap {
-        TypeMap { name_map: HashMap::new(), sig_map: HashMap::new() }
+        TypeMap { name_map: HashMap::new(), interned: HashSet::new() }
     }
 
-    pub fn insert(&mut self, name: String, type_def: Arc<Type>) -> Result<(), ParseError> {
-        if self.name_map.contains_key(&name) {
-            return Err(ParseError::BadExpress("Can't overwrite existing types".to_string()))
-        } else {
-            self.name_map.insert(name, type_def.clone());
+    pub fn intern(&mut self, type_def: Arc<Type>) -> Arc<Type> {
+        if let Some(existing) = self.interned.get(&type_def) {
+            return existing.clone();
         }
-        let sig = type_def.get_sig();
-        if !self.sig_map.contains_key(&sig) {
-            self.sig_map.insert(type_def.get_sig(), type_def);
+        self.interned.insert(type_def.clone());
+        type_def
+    }
+
+    pub fn insert(&mut self, name: String, type_def: Arc<Type>, span: Span) -> Result<(), ParseError> {
+        let type_def = self.intern(type_def);
+        match self.name_map.entry(name) {
+            Entry::Occupied(_) => {
+                return Err(ParseError::BadExpress(span, "Can't overwrite existing types".to_string()))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(type_def);
+            }
         }
         Ok(())
     }
@@ -41,168 +99,346 @@ impl TypeMap {
 
 impl<'a> Parser<'a> {
     pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let current_token = lexer.next_token();
-        Parser { lexer, current_token}
+        let first = lexer.next_token();
+        Parser {
+            lexer,
+            current_token: first.node,
+            current_span: first.span,
+            prev_span: Span::default(),
+            diagnostics: Vec::new(),
+            depth: 0,
+        }
     }
 
     fn advance(&mut self) {
-        self.current_token = self.lexer.next_token();
+        match self.current_token {
+            Token::LeftBracket => self.depth += 1,
+            Token::RightBracket => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        let next = self.lexer.next_token();
+        self.prev_span = self.current_span;
+        self.current_token = next.node;
+        self.current_span = next.span;
+    }
+
+    fn peek_token(&self) -> Token {
+        self.lexer.clone().next_token().node
     }
 
     fn expect(&mut self, expected: Token, msg: &'static str) -> Result<(), ParseError> {
         if self.current_token == expected {
             self.advance();
             Ok(())
+        } else if self.current_token == Token::EOF {
+            Err(ParseError::UnexpectedEOF(self.current_span))
         } else {
-            Err(ParseError::UnexpectedToken(self.current_token.clone(), msg.to_string()))
+            Err(ParseError::UnexpectedToken(self.current_token.clone(), self.current_span, msg.to_string()))
         }
     }
 
-    pub fn parse(&mut self) -> Result<Expr, ParseError> {
+    // closing bar of a function signature; nested signatures like
+    // |int -> |int -> int|| end in a run of bars that the lexer reads as
+    // `||` or `|||`, so peel a single bar off of those
+    fn expect_fn_close(&mut self) -> Result<(), ParseError> {
+        let rest = match self.current_token {
+            Token::FnTypes => {
+                self.advance();
+                return Ok(());
+            }
+            Token::Operator(ref op) if **op == Operator::Or => Token::FnTypes,
+            Token::Operator(ref op) if **op == Operator::BitOr => Token::Operator(Operator::Or.into()),
+            _ => return self.expect(Token::FnTypes, "Expected func param close"),
+        };
+        self.prev_span = Span { end: self.current_span.start + 1, ..self.current_span };
+        self.current_span = Span { start: self.current_span.start + 1, col: self.current_span.col + 1, ..self.current_span };
+        self.current_token = rest;
+        Ok(())
+    }
+
+    fn bad_token(&self, msg: &str) -> ParseError {
+        ParseError::BadToken(self.current_token.clone(), self.current_span, msg.to_string())
+    }
+
+    // span from start up to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.prev_span)
+    }
+
+    pub fn parse(&mut self) -> (Expr, Vec<Diagnostic>) {
         let mut variables = HashMap::new();
         let mut types = TypeMap::new();
-        self.parse_block(true, &mut variables, &mut types)
+        let start = self.current_span;
+        let ast = match self.parse_block(true, &mut variables, &mut types) {
+            Ok(ast) => ast,
+            Err(err) => {
+                self.diagnostics.push(err.into());
+                Expr::new(ExprKind::Block(Vec::new()), self.span_from(start))
+            }
+        };
+        (ast, std::mem::take(&mut self.diagnostics))
+    }
+
+    // panic-mode recovery: skip ahead to the end of the current statement,
+    // the end of the enclosing block, or the start of a top-level type def.
+    // block_depth is the depth inside the block being recovered, so any
+    // brackets the failed statement already opened get closed first
+    fn synchronize(&mut self, is_main: bool, block_depth: usize) {
+        loop {
+            let in_block = self.depth == block_depth;
+            match self.current_token {
+                Token::EOF => return,
+                Token::Semicolon if in_block => {
+                    self.advance();
+                    return;
+                }
+                Token::RightBracket if in_block && !is_main => return,
+                Token::Identifier(_) if in_block && is_main && self.peek_token() == Token::Colon => return,
+                _ => {}
+            }
+            self.advance();
+        }
     }
 
     pub fn parse_block(
         &mut self, 
         is_main: bool,
-        mut variables: &mut HashMap<String, Arc<Expr>>, 
-        mut types: &mut TypeMap
+        variables: &mut HashMap<String, Arc<Expr>>, 
+        types: &mut TypeMap
     ) -> Result<Expr, ParseError> {
+        let start = self.current_span;
         if !is_main {
             self.expect(Token::LeftBracket, "Expected starting bracket at start of block")?;
         }
+        let block_depth = self.depth;
         let mut exprs = Vec::new();
-        let mut prior_expr = None;
         while self.current_token != Token::EOF {
-            let expr = self.parse_expr(&mut variables, &mut types)?;
-            println!("{:?}", self.current_token);
-            exprs.push(expr.clone());
-            prior_expr = Some(expr);
+            if !is_main && self.current_token == Token::RightBracket {
+                break;
+            }
+            match self.parse_expr(variables, types) {
+                Ok(expr) => exprs.push(expr),
+                Err(err) => {
+                    self.diagnostics.push(err.into());
+                    self.synchronize(is_main, block_depth);
+                    continue;
+                }
+            }
             match self.current_token {
-                Token::RightBracket => {
+                Token::RightBracket if !is_main => {
                     break;
                 }
                 Token::Semicolon => {
                     self.advance();
                 }
                 _ => {
-                    return Err(ParseError::BadToken(self.current_token.clone(), "Expected a semicolon or a closing bracket to block".to_string()))
+                    let err = self.bad_token("Expected a semicolon or a closing bracket to block");
+                    self.diagnostics.push(err.into());
+                    self.synchronize(is_main, block_depth);
                 }
             }
         }
 
-        println!("Declared variables: {:?}", variables);
         if !is_main {
-            self.expect(Token::RightBracket, "Expected closing bracket at end of block")?;
-        } else {
-            self.expect(Token::EOF, "Expected EOF token at end of main block")?;
+            if let Err(err) = self.expect(Token::RightBracket, "Expected closing bracket at end of block") {
+                self.diagnostics.push(err.into());
+            }
         }
-        Ok(Expr::Block(exprs))
+        Ok(Expr::new(ExprKind::Block(exprs), self.span_from(start)))
     }
 
     fn parse_expr(
         &mut self, 
-        mut variables: &mut HashMap<String, Arc<Expr>>, 
-        mut types: &mut TypeMap
+        variables: &mut HashMap<String, Arc<Expr>>, 
+        types: &mut TypeMap
+    ) -> Result<Arc<Expr>, ParseError> {
+        self.parse_expr_bp(0, variables, types)
+    }
+
+    fn parse_expr_bp(
+        &mut self, 
+        min_bp: u8,
+        variables: &mut HashMap<String, Arc<Expr>>, 
+        types: &mut TypeMap
+    ) -> Result<Arc<Expr>, ParseError> {
+        let mut left_expr = self.parse_primary(variables, types)?;
+        while let Token::Operator(ref op) = self.current_token {
+            let op = op.clone();
+            let Some((l_bp, r_bp)) = infix_binding_power(&op) else {
+                break;
+            };
+            if l_bp < min_bp {
+                break;
+            }
+            self.advance();
+            let right_expr = self.parse_expr_bp(r_bp, variables, types)?;
+            left_expr = Expr::binary(left_expr, op, right_expr).into();
+        }
+        Ok(left_expr)
+    }
+
+    fn parse_primary(
+        &mut self, 
+        variables: &mut HashMap<String, Arc<Expr>>, 
+        types: &mut TypeMap
     ) -> Result<Arc<Expr>, ParseError> {
+        let start = self.current_span;
         let left_expr: Arc<Expr> = match self.current_token {
             Token::Param => {
                 self.advance();
-                Expr::Param.into()
+                Expr::new(ExprKind::Param, start).into()
+            }
+            Token::Operator(ref op) if **op == Operator::Mul => {
+                self.advance();
+                let shared = self.current_token == Token::Operator(Operator::Mul.into());
+                if shared {
+                    self.advance();
+                }
+                let expr = self.parse_expr_bp(PREFIX_BINDING_POWER, variables, types)?;
+                Expr::new(ExprKind::Mut { expr, shared }, self.span_from(start)).into()
+            }
+            Token::Operator(ref op) if **op == Operator::Lt => {
+                self.advance();
+                let expr = self.parse_expr_bp(UNION_BINDING_POWER, variables, types)?;
+                self.expect(Token::Operator(Operator::Gt.into()), "Expected `>` to close the union value")?;
+                Expr::new(ExprKind::Union(expr), self.span_from(start)).into()
             }
             Token::Operator(ref op) => {
                 let op = op.clone();
                 self.advance();
-                self.parse_unary(&mut variables, &mut types, op)?.into()
+                self.parse_unary(variables, types, op, start)?.into()
             }
             Token::TNone => {
                 self.advance();
-                Expr::Option(None).into()
+                Expr::new(ExprKind::Option(None), start).into()
             }
             Token::TSome => {
                 self.advance();
                 self.expect(Token::LeftParen, "Expected opening paren and expression after optional")?;
                 let expr = self.parse_expr(variables, types)?;
                 self.expect(Token::RightParen, "Expected opening paren and expression after optional")?;
-                Expr::Option(Some(expr.into())).into()
+                Expr::new(ExprKind::Option(Some(expr)), self.span_from(start)).into()
             }
             Token::Bool(b) => {
                 self.advance();
-                Expr::Bool(b).into()
+                Expr::new(ExprKind::Bool(b), start).into()
             }
-            Token::Int(i) => {
+            Token::Int(ref i) => {
+                let i = i.clone();
                 self.advance();
-                Expr::Int(i).into()
+                Expr::new(ExprKind::Int(i), start).into()
             }
-            Token::Uint(u) => {
+            Token::Uint(ref u) => {
+                let u = u.clone();
                 self.advance();
-                Expr::Uint(u).into()
+                Expr::new(ExprKind::Uint(u), start).into()
             }
             Token::Char(c) => {
                 self.advance();
-                Expr::Char(c).into()
+                Expr::new(ExprKind::Char(c), start).into()
             }
             Token::Float(f) => {
                 self.advance();
-                Expr::Float(f).into()
+                Expr::new(ExprKind::Float(f), start).into()
             }
             Token::String(ref s) => {
                 let s = s.clone();
                 self.advance();
-                Expr::String(s).into()
+                Expr::new(ExprKind::String(s), start).into()
             }
             Token::Identifier(ref name) => {
                 let name = name.clone();
                 self.advance();
-                self.parse_identifer(name, variables, types)?.into()
+                self.parse_identifer(name, start, variables, types)?
             }
             Token::LeftParen => {
                 self.advance();
-                let expr = self.parse_expr(variables, types)?;
-                self.expect(Token::RightParen, "Expected closing paren after parsing inner expression")?;
-                expr.into()
+                self.parse_tuple(variables, types, start)?
             }
             Token::FnTypes => {
                 self.advance();
                 let pt = self.parse_type(types)?;
                 self.expect(Token::Arrow, "Expected arrow function")?;
                 let rt = self.parse_type(types)?;
-                self.expect(Token::FnTypes, "Expected func param close")?;
-                let block = self.parse_block(false, &mut HashMap::new(), types)?;
+                self.expect_fn_close()?;
+                let block = self.parse_block(false, variables, types)?;
 
-                Expr::Function { param_sig: pt, return_sig: rt, block: block.into() }.into()
+                Expr::new(ExprKind::Function { param_sig: pt, return_sig: rt, block: block.into() }, self.span_from(start)).into()
             }
             Token::LeftBracket => {
                 self.advance();
-                self.parse_struct(variables, types)?
+                self.parse_struct(variables, types, start)?
+            }
+            Token::LeftBrace => {
+                self.advance();
+                let mut elements = Vec::new();
+                while self.current_token != Token::RightBrace {
+                    elements.push(self.parse_expr(variables, types)?);
+                    if self.current_token == Token::Comma {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RightBrace, "Expected closing bracket after array elements")?;
+                Expr::new(ExprKind::Array(elements), self.span_from(start)).into()
             }
             Token::If => {
                 self.advance();
-                self.parse_if(variables, types)?
+                self.parse_if(variables, types, start)?
+            }
+            Token::Return => {
+                self.advance();
+                let expr = self.parse_expr(variables, types)?;
+                return Ok(Expr::new(ExprKind::Return(expr), self.span_from(start)).into());
             }
             _ => {
-                return Err(ParseError::BadToken(self.current_token.clone(), "Found wrong token while parsing expression".to_string()))
+                return Err(self.bad_token("Found wrong token while parsing expression"))
             }
         };
-        let left_expr = self.parse_method_call(variables,types, left_expr)?;
-        match self.current_token {
-            Token::Operator(ref op) => {
-                let op = op.clone();
+        self.parse_method_call(variables,types, left_expr)
+    }
+
+    // `(a)` is just a grouped expression, `(a,)` and `(a, b)` are tuples and
+    // `()` is None, as in the spec
+    fn parse_tuple(
+        &mut self, 
+        variables: &mut HashMap<String, Arc<Expr>>, 
+        types: &mut TypeMap,
+        start: Span,
+    ) -> Result<Arc<Expr>, ParseError> {
+        if self.current_token == Token::RightParen {
+            self.advance();
+            return Ok(Expr::new(ExprKind::Option(None), self.span_from(start)).into());
+        }
+        let mut elements = Vec::new();
+        while self.current_token != Token::RightParen {
+            let expr = self.parse_expr(variables, types)?;
+            if elements.is_empty() && self.current_token == Token::RightParen {
                 self.advance();
-                self.parse_binary(&mut variables,&mut types, op, left_expr)
+                return Ok(expr);
             }
-            _ => {
-                Ok(left_expr)
+            elements.push(expr);
+            if self.current_token == Token::Comma {
+                self.advance();
+            } else {
+                break;
             }
         }
+        self.expect(Token::RightParen, "Expected closing paren after tuple elements")?;
+        let pairs = elements
+            .into_iter()
+            .enumerate()
+            .map(|(i, expr)| (i.to_string(), expr))
+            .collect();
+        Ok(Expr::new(ExprKind::Struct { pairs }, self.span_from(start)).into())
     }
 
     fn parse_struct(
         &mut self, 
         variables: &mut HashMap<String, Arc<Expr>>, 
         types: &mut TypeMap,
+        start: Span,
     ) -> Result<Arc<Expr>, ParseError> {
         let mut var_defs= HashMap::new();
         while self.current_token!=Token::EOF {
@@ -220,11 +456,11 @@ impl<'a> Parser<'a> {
                     var_defs.insert(name.to_string(), expr);
                 }
                 _ => {
-                    return Err(ParseError::BadToken(self.current_token.clone(), "Expected expresion or unary operator".to_string()))
+                    return Err(self.bad_token("Expected expresion or unary operator"))
                 }
             }
         }
-        Ok(Expr::Struct { pairs: var_defs }.into())
+        Ok(Expr::new(ExprKind::Struct { pairs: var_defs }, self.span_from(start)).into())
     }
 
 
@@ -232,9 +468,10 @@ impl<'a> Parser<'a> {
         &mut self, 
         variables: &mut HashMap<String, Arc<Expr>>, 
         types: &mut TypeMap,
+        start: Span,
     ) -> Result<Arc<Expr>, ParseError> {
         let expr = self.parse_expr(variables, types)?;
-        let then_block = self.parse_block(false, variables, types)?.into();
+        let then_block: Arc<Expr> = self.parse_block(false, variables, types)?.into();
         let else_block = match self.current_token {
             Token::Else => {
                 self.advance();
@@ -244,34 +481,21 @@ impl<'a> Parser<'a> {
                 None
             }
         };
+        let span = self.span_from(start);
 
-        match *expr {
-            Expr::Bool(b) => {
-                if b {
-                    Ok(then_block)
-                } else {
-                    if let Some(else_block) = else_block {
-                        Ok(else_block.into())
-                    } else {
-                        Ok(Expr::Option(None).into())
-                    }
-                }
-            }
-            _ => {
-                if let Some(else_block) = else_block {
-                    Ok(Expr::If { 
-                        condition: expr, 
-                        then_branch: then_block,
-                        else_branch: else_block.into()
-                    }.into())
-                } else {
-                    Ok(Expr::If { 
-                        condition: expr, 
-                        then_branch: Expr::Option(Some(then_block)).into(), 
-                        else_branch: Expr::Option(None).into()
-                    }.into())
-                }
-            }
+        if let Some(else_block) = else_block {
+            Ok(Expr::new(ExprKind::If { 
+                condition: expr, 
+                then_branch: then_block,
+                else_branch: else_block.into()
+            }, span).into())
+        } else {
+            let then_span = then_block.span;
+            Ok(Expr::new(ExprKind::If { 
+                condition: expr, 
+                then_branch: Expr::new(ExprKind::Option(Some(then_block)), then_span).into(), 
+                else_branch: Expr::new(ExprKind::Option(None), span).into()
+            }, span).into())
         }
     }
 
@@ -282,106 +506,101 @@ impl<'a> Parser<'a> {
         mut left_expr: Arc<Expr>
     ) -> Result<Arc<Expr>, ParseError> {
         if self.current_token==Token::Period {
-            match *left_expr {
-                Expr::Type(_) => {
-                    return Err(ParseError::BadToken(self.current_token.clone(), "Can't call methods on types".to_string()))
-                }
-                _ => {}
+            if let ExprKind::Type(_) = left_expr.kind {
+                return Err(self.bad_token("Can't call methods on types"))
             }
         }
-        while self.current_token==Token::Period {
+        let start = left_expr.span;
+        loop {
+            if self.current_token == Token::LeftParen {
+                self.advance();
+                let arg = self.parse_expr(variables, types)?;
+                self.expect(Token::RightParen, "Expected right paren for function call")?;
+                left_expr = Expr::new(ExprKind::Call { callee: left_expr, arg }, self.span_from(start)).into();
+                continue;
+            }
+            if let Token::TupleIndex(i) = self.current_token {
+                self.advance();
+                let field = i.to_string().into();
+                left_expr = Expr::new(ExprKind::FieldAccess { context: left_expr, field }, self.span_from(start)).into();
+                continue;
+            }
+            if self.current_token != Token::Period {
+                break;
+            }
             self.advance();
             match self.current_token {
+                Token::Identifier(ref name) if self.peek_token() != Token::LeftParen => {
+                    let field = name.clone();
+                    self.advance();
+                    left_expr = Expr::new(ExprKind::FieldAccess { context: left_expr, field }, self.span_from(start)).into();
+                }
                 Token::Identifier(ref name) => {
                     let name = name.clone();
                     self.advance();
                     self.expect(Token::LeftParen, "Expected Leftparen for method call")?;
-                    let right_expr = self.parse_expr(variables, types)?;
-                    left_expr = Expr::MethodCall { name, context: left_expr, param: right_expr, type_def: None }.into();
+                    // `.is(T)` takes a type rather than a value
+                    let right_expr = if name.as_str() == "is" {
+                        let type_start = self.current_span;
+                        let t = self.parse_type(types)?;
+                        Expr::new(ExprKind::Type((*t).clone()), self.span_from(type_start)).into()
+                    } else {
+                        self.parse_expr(variables, types)?
+                    };
                     self.expect(Token::RightParen, "Expected right paren for method call")?;
+                    left_expr = Expr::new(ExprKind::MethodCall { name, context: left_expr, param: right_expr, type_def: None }, self.span_from(start)).into();
                 }
                 Token::Assign => {
                     self.advance();
                     self.expect(Token::LeftParen, "Expected Leftparen for method call")?;
                     let right_expr = self.parse_expr(variables, types)?;
-                    left_expr = Expr::Assign {context: left_expr, param: right_expr, type_def: None }.into();
                     self.expect(Token::RightParen, "Expected right paren for method call")?;
+                    left_expr = Expr::new(ExprKind::Assign {context: left_expr, param: right_expr, type_def: None }, self.span_from(start)).into();
                 }
                 Token::ImplAssign => {
+                    self.diagnostics.push(Diagnostic::warning(
+                        ErrorCode::Unsupported,
+                        self.current_span,
+                        "`impl_as` is not implemented yet and behaves like `as`",
+                    ));
                     self.advance();
                     self.expect(Token::LeftParen, "Expected Leftparen for method call")?;
                     let right_expr = self.parse_expr(variables, types)?;
-                    left_expr = Expr::Assign {context: left_expr, param: right_expr, type_def: None }.into();
                     self.expect(Token::RightParen, "Expected right paren for method call")?;
+                    left_expr = Expr::new(ExprKind::Assign {context: left_expr, param: right_expr, type_def: None }, self.span_from(start)).into();
+                }
+                Token::AssignShared => {
+                    self.advance();
+                    self.expect(Token::LeftParen, "Expected Leftparen for method call")?;
+                    let right_expr = self.parse_expr(variables, types)?;
+                    self.expect(Token::RightParen, "Expected right paren for method call")?;
+                    left_expr = Expr::new(ExprKind::AssignShared { context: left_expr, param: right_expr }, self.span_from(start)).into();
                 }
                 Token::PassTo => {
                     self.advance();
                     self.expect(Token::LeftParen, "Expected Leftparen for method call")?;
                     let right_expr = self.parse_expr(variables, types)?;
-                    left_expr = Expr::PassTo {context: left_expr, param: right_expr, type_def: None }.into();
                     self.expect(Token::RightParen, "Expected right paren for method call")?;
+                    left_expr = Expr::new(ExprKind::PassTo {context: left_expr, param: right_expr, type_def: None }, self.span_from(start)).into();
                 }
                 _ => {
-                    unimplemented!("method call");
+                    return Err(self.bad_token("Expected a method, field or tuple index after `.`"));
                 }
             }
         }
         Ok(left_expr)
     }
 
-    fn parse_binary(
-        &mut self, 
-        mut variables: &mut HashMap<String, Arc<Expr>>,mut types: &mut TypeMap, op: Arc<Operator>, left_expr: Arc<Expr>) -> Result<Arc<Expr>, ParseError> {
-        let right_expr: Arc<Expr> = self.parse_expr(&mut variables, &mut types)?;
-        match *op {
-            Operator::Add => {
-                Ok(Expr::add(left_expr, right_expr)?.into())
-            }
-            Operator::Sub=> {
-                Ok(Expr::sub(left_expr, right_expr)?.into())
-            }
-            Operator::Mul=> {
-                Ok(Expr::mult(left_expr, right_expr)?.into())
-            }
-            Operator::Div=> {
-                Ok(Expr::div(left_expr, right_expr)?.into())
-            }
-            Operator::Mod=> {
-                Ok(Expr::modd(left_expr, right_expr)?.into())
-            }
-            Operator::Eq=> {
-                Ok(Expr::eq(left_expr, right_expr)?.into())
-            }
-            Operator::Neq=> {
-                Ok(Expr::neq(left_expr, right_expr)?.into())
-            }
-            _ => {
-                Ok(Expr::BinaryOp{left: left_expr, op, right: right_expr}.into())
-            }
-        }
-    }
 
-    fn parse_unary(&mut self, mut variables: &mut HashMap<String, Arc<Expr>>, mut types: &mut TypeMap, op: Arc<Operator>) -> Result<Expr, ParseError> {
-        let right_expr: Arc<Expr> = self.parse_expr(&mut variables, &mut types)?;
+    fn parse_unary(&mut self, variables: &mut HashMap<String, Arc<Expr>>, types: &mut TypeMap, op: Arc<Operator>, start: Span) -> Result<Expr, ParseError> {
+        let right_expr: Arc<Expr> = self.parse_expr_bp(PREFIX_BINDING_POWER, variables, types)?;
+        let span = self.span_from(start);
         match *op {
-            Operator::Not => {
-                Ok(Expr::UnaryOp{op: op.clone(), expr:right_expr})
-            }
-            Operator::Sub => {
-                match *right_expr {
-                    Expr::Int(i) => {
-                        Ok(Expr::Int(-i))
-                    }
-                    Expr::Float(f) => {
-                        Ok(Expr::Float(-f))
-                    }
-                    _ => {
-                        Err(ParseError::BadToken(self.current_token.clone(), "Expected floating point or integer following negative unary operator".to_string()))
-                    }
-                }
+            Operator::Not | Operator::Sub => {
+                Ok(Expr::new(ExprKind::UnaryOp{op: op.clone(), expr:right_expr}, span))
             }
             _ => {
-                Err(ParseError::BadToken(self.current_token.clone(), "Expected expresion or unary operator".to_string()))
+                Err(ParseError::BadToken(self.current_token.clone(), start, "Expected expresion or unary operator".to_string()))
             }
         }
     }
@@ -392,10 +611,84 @@ impl<'a> Parser<'a> {
                 self.advance();
                 Ok(Type::None.into())
             }
+            Token::Generic => {
+                self.advance();
+                Ok(Type::Generic.into())
+            }
             Token::Option => {
                 self.advance();
                 let t = self.parse_type(types)?;
-                Ok(Type::Optional { type_def: t.into() }.into())
+                Ok(Type::optional(t).into())
+            }
+            // `*T` and `**T`
+            Token::Operator(ref op) if **op == Operator::Mul => {
+                self.advance();
+                let shared = self.current_token == Token::Operator(Operator::Mul.into());
+                if shared {
+                    self.advance();
+                }
+                let t = self.parse_type(types)?;
+                Ok(Type::Mut { type_def: t, shared }.into())
+            }
+            // `<A | B>`; members may also be separated by commas
+            Token::Operator(ref op) if **op == Operator::Lt => {
+                let start = self.current_span;
+                self.advance();
+                let mut members = Vec::new();
+                loop {
+                    members.push(self.parse_type(types)?);
+                    if matches!(self.current_token, Token::FnTypes | Token::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::Operator(Operator::Gt.into()), "Expected `>` to close the union type")?;
+                if let Some(t) = Type::duplicate_member(&members) {
+                    return Err(ParseError::BadExpress(
+                        self.span_from(start),
+                        format!("{} appears more than once in the union; use a ghost type to tell them apart", t),
+                    ));
+                }
+                Ok(Type::Optional { members }.into())
+            }
+            Token::LeftBrace => {
+                self.advance();
+                let t = self.parse_type(types)?;
+                self.expect(Token::RightBrace, "Expected closing bracket after array type")?;
+                Ok(Type::Array { array_type: t }.into())
+            }
+            Token::LeftParen => {
+                self.advance();
+                // `()` is the None type
+                if self.current_token == Token::RightParen {
+                    self.advance();
+                    return Ok(Type::None.into());
+                }
+                let mut elements = Vec::new();
+                while self.current_token != Token::RightParen {
+                    let t = self.parse_type(types)?;
+                    if elements.is_empty() && self.current_token == Token::RightParen {
+                        self.advance();
+                        return Ok(t);
+                    }
+                    elements.push(t);
+                    if self.current_token == Token::Comma {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RightParen, "Expected closing paren after tuple type")?;
+                Ok(Type::tuple(elements).into())
+            }
+            Token::FnTypes => {
+                self.advance();
+                let param_type = self.parse_type(types)?;
+                self.expect(Token::Arrow, "Expected arrow in function type")?;
+                let return_type = self.parse_type(types)?;
+                self.expect_fn_close()?;
+                Ok(Type::Function { param_type, return_type }.into())
             }
             Token::TBool => {
                 self.advance();
@@ -434,12 +727,12 @@ impl<'a> Parser<'a> {
                             let name = name.clone();
                             self.advance();
                             self.expect(Token::Colon, "Expected type definition")?;
-                            let t = self.parse_type(types)?.into();
+                            let t = self.parse_type(types)?;
                             self.expect(Token::Comma, "Expected comma after type def")?;
                             type_defs.push(Type::TypeDef { name, type_def: t }.into())
                         }
                         _ => {
-                            return Err(ParseError::BadToken(self.current_token.clone(), "Expected expresion or unary operator".to_string()))
+                            return Err(self.bad_token("Expected expresion or unary operator"))
                         }
                     }
                 }
@@ -449,48 +742,175 @@ impl<'a> Parser<'a> {
                 let name = name.clone();
                 self.advance();
                 if let Some(t) = types.name_map.get(&*name) {
-                    Ok(t.clone())
+                    // keep the alias name around for diagnostics; TypeDef is
+                    // transparent to everything else
+                    Ok(Type::TypeDef { name, type_def: t.clone() }.into())
                 } else {
-                    return Err(ParseError::BadToken(self.current_token.clone(), "Expected expresion or unary operator".to_string()));
+                    Err(ParseError::BadToken(Token::Identifier(name), self.prev_span, "Expected expresion or unary operator".to_string()))
                 }
             }
             _ => {
-                Err(ParseError::BadToken(self.current_token.clone(), "Expected expresion or unary operator".to_string()))
+                Err(self.bad_token("Expected expresion or unary operator"))
             }
         }
     }
 
-    fn parse_type_def(&mut self, name: Arc<String>, types: &mut TypeMap) -> Result<Expr, ParseError> {
-        let t: Arc<Type> = self.parse_type(types)?.into();
-        types.insert(name.to_string(), t.clone())?;
-        Ok(Expr::Type(Type::TypeDef { name, type_def: t}))
+    fn parse_type_def(&mut self, name: Arc<String>, start: Span, types: &mut TypeMap) -> Result<Expr, ParseError> {
+        let ghost = self.current_token == Token::Ghost;
+        if ghost {
+            self.advance();
+        }
+        let mut t: Arc<Type> = self.parse_type(types)?;
+        if ghost {
+            t = Type::Ghost { name: name.clone(), type_def: t }.into();
+        }
+        let span = self.span_from(start);
+        types.insert(name.to_string(), t.clone(), span)?;
+        Ok(Expr::new(ExprKind::Type(Type::TypeDef { name, type_def: t}), span))
     }
 
     fn parse_identifer(
         &mut self, 
         name: Arc<String>, 
+        start: Span,
         variables: &mut HashMap<String, Arc<Expr>>,
         types: &mut TypeMap
     ) -> Result<Arc<Expr>, ParseError> {
         match self.current_token {
             Token::Colon => {
                 self.advance();
-                Ok(self.parse_type_def(name, types)?.into())
+                Ok(self.parse_type_def(name, start, types)?.into())
             }
-            Token::LeftParen => {
-                unimplemented!("function call");
+            // `Name::from(..)`, or `Name.from(..)` as the spec writes it
+            Token::Period if types.name_map.contains_key(&*name) && matches!(self.peek_token(), Token::Identifier(ref f) if f.as_str() == "from") => {
+                self.parse_conversion(name, start, variables, types)
             }
+            Token::PathSep => self.parse_conversion(name, start, variables, types),
             _ => {
                 match variables.get(&*name) {
                     Some(expr) => {
                         Ok(expr.clone())
                     }
                     None => {
-                        Ok(Expr::Identifier(name).into())
+                        Ok(Expr::new(ExprKind::Identifier(name), start).into())
                     }
                 }
             }
         }
     }
+
+    fn parse_conversion(
+        &mut self,
+        name: Arc<String>,
+        start: Span,
+        variables: &mut HashMap<String, Arc<Expr>>,
+        types: &mut TypeMap
+    ) -> Result<Arc<Expr>, ParseError> {
+        self.advance();
+        let Some(t) = types.name_map.get(&*name).cloned() else {
+            return Err(ParseError::BadExpress(self.span_from(start), format!("Unknown type `{}`", name)));
+        };
+        let target = Type::TypeDef { name, type_def: t }.into();
+        match self.current_token {
+            Token::Identifier(ref f) if f.as_str() == "from" => self.advance(),
+            _ => return Err(self.bad_token("Expected `from` after the type name")),
+        }
+        self.expect(Token::LeftParen, "Expected Leftparen for conversion")?;
+        let expr = self.parse_expr(variables, types)?;
+        self.expect(Token::RightParen, "Expected right paren for conversion")?;
+        Ok(Expr::new(ExprKind::Convert { target, expr }, self.span_from(start)).into())
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parses a single statement and prints it fully parenthesised
+    fn parse(source: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(source));
+        let (ast, diagnostics) = parser.parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let ExprKind::Block(exprs) = ast.kind else {
+            panic!("expected a block, found {:?}", ast);
+        };
+        assert_eq!(exprs.len(), 1);
+        sexpr(&exprs[0])
+    }
+
+    fn error_count(source: &str) -> usize {
+        let (_, diagnostics) = Parser::new(Lexer::new(source)).parse();
+        diagnostics.iter().filter(|d| d.is_error()).count()
+    }
+
+    fn sexpr(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Identifier(name) => name.to_string(),
+            ExprKind::Int(i) => i.to_string(),
+            ExprKind::BinaryOp { left, op, right } => format!("({:?} {} {})", op, sexpr(left), sexpr(right)),
+            ExprKind::UnaryOp { op, expr } => format!("({:?} {})", op, sexpr(expr)),
+            ExprKind::MethodCall { name, context, param, .. } => format!("(.{} {} {})", name, sexpr(context), sexpr(param)),
+            ExprKind::FieldAccess { context, field } => format!("(.{} {})", field, sexpr(context)),
+            ExprKind::Call { callee, arg } => format!("(call {} {})", sexpr(callee), sexpr(arg)),
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(parse("a - b - c;"), "(Sub (Sub a b) c)");
+        assert_eq!(parse("a / b * c;"), "(Mul (Div a b) c)");
+        assert_eq!(parse("a == b != c;"), "(Neq (Eq a b) c)");
+        assert_eq!(parse("a || b || c;"), "(Or (Or a b) c)");
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        assert_eq!(parse("a += b -= c;"), "(AssignOp(Add) a (AssignOp(Sub) b c))");
+    }
+
+    #[test]
+    fn each_level_binds_tighter_than_the_one_before() {
+        // lowest to highest, one operator per level of the table
+        let levels = ["+=", "||", "&&", "==", "<", "|||", "^", "&", "+", "*"];
+        for pair in levels.windows(2) {
+            let (loose, tight) = (parse_op(pair[0]), parse_op(pair[1]));
+            // the tighter operator ends up innermost on either side
+            assert_eq!(parse(&format!("a {} b {} c;", pair[0], pair[1])), format!("({:?} a ({:?} b c))", loose, tight));
+            assert_eq!(parse(&format!("a {} b {} c;", pair[1], pair[0])), format!("({:?} ({:?} a b) c)", loose, tight));
+        }
+    }
+
+    fn parse_op(op: &str) -> Operator {
+        let mut lexer = Lexer::new(op);
+        match lexer.next_token().node {
+            Token::Operator(op) => (*op).clone(),
+            token => panic!("{} is not an operator: {:?}", op, token),
+        }
+    }
+
+    #[test]
+    fn prefix_operators_bind_tighter_than_infix() {
+        assert_eq!(parse("-a * b;"), "(Mul (Sub a) b)");
+        assert_eq!(parse("!a && b;"), "(And (Not a) b)");
+    }
+
+    #[test]
+    fn method_chains_bind_tighter_than_operators() {
+        assert_eq!(parse("a.b(x) + c;"), "(Add (.b a x) c)");
+        assert_eq!(parse("c + a.b(x);"), "(Add c (.b a x))");
+        assert_eq!(parse("a.b(x).c(y) * d.e;"), "(Mul (.c (.b a x) y) (.e d))");
+        assert_eq!(parse("-a.b(x);"), "(Sub (.b a x))");
+        assert_eq!(parse("(a + b).c(x);"), "(.c (Add a b) x)");
+    }
+
+    #[test]
+    fn recovery_reports_one_error_per_broken_statement() {
+        assert_eq!(error_count("1 +; 2; 3 +; 4 +;"), 3);
+        assert_eq!(error_count("{a: 1 b: 2,}; 3;"), 1);
+        assert_eq!(error_count("|int -> int| { {a: 1 b: 2,}; 5 }.as(f); 1 +;"), 2);
+        assert_eq!(error_count("|int -> int| { 1 + ; {a: [1, 2 3],}; @ }.as(f);"), 2);
+        // a type def starts a new statement even without the `;`
+        assert_eq!(error_count("1 + )\nPoint: {x: int,};\n{x: 1,}.as(p);"), 1);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// fixed set of worker threads taking jobs off a shared queue, in the order
+// they were sent
+#[derive(Debug)]
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || Self::work(&receiver))
+            })
+            .collect();
+        ThreadPool { sender: Some(sender), workers }
+    }
+
+    // one worker per core the os will give us
+    pub fn with_available_parallelism() -> ThreadPool {
+        ThreadPool::new(thread::available_parallelism().map_or(4, |n| n.get()))
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = &self.sender {
+            // the workers only hang up once the pool is dropped
+            let _ = sender.send(Box::new(job));
+        }
+    }
+
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // the lock is only held while taking the next job off the queue
+            let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+            match job {
+                Ok(job) => job(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    // lets the queued jobs finish, then stops the workers
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// result of a job that may not have finished yet; wait blocks until it has
+#[derive(Debug)]
+pub struct Pending<T> {
+    result: Mutex<Option<T>>,
+    ready: Condvar,
+}
+
+impl<T: Clone> Pending<T> {
+    pub fn new() -> Pending<T> {
+        Pending { result: Mutex::new(None), ready: Condvar::new() }
+    }
+
+    pub fn complete(&self, value: T) {
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+        self.ready.notify_all();
+    }
+
+    pub fn wait(&self) -> T {
+        let mut result = self.result.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(value) = &*result {
+                return value.clone();
+            }
+            result = self.ready.wait(result).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+// two pending values are only the same if they wait on the same job
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
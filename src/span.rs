@@ -0,0 +1,33 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl Span {
+    pub fn new(start: u32, end: u32, line: u32, col: u32) -> Span {
+        Span { start, end, line, col }
+    }
+
+    // covers everything from the start of self to the end of other
+    pub fn to(self, other: Span) -> Span {
+        if other.end < self.start {
+            return self;
+        }
+        Span { start: self.start, end: other.end.max(self.end), line: self.line, col: self.col }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Spanned<T> {
+        Spanned { node, span }
+    }
+}
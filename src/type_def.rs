@@ -1,6 +1,10 @@
-use std::{sync::Arc, ops::Deref};
+use std::{fmt, mem, sync::Arc};
+use std::hash::{DefaultHasher, Hash, Hasher};
 
-#[derive(Debug, Ord, Eq, PartialOrd, Clone)]
+use crate::lexer::Operator;
+
+#[derive(Debug, Eq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Type {
     TypeDef {
         name: Arc<String>,
@@ -24,20 +28,156 @@ pub enum Type {
         param_type: Arc<Type>,
         return_type: Arc<Type>,
     },
+    // union of distinct member types, `<A | B>`; `?T` is `<T | None>`
     Optional {
+        members: Vec<Arc<Type>>,
+    },
+    // nominal type declared with `Name: ghost T;`; it shares T's
+    // representation but is only equal to itself
+    Ghost {
+        name: Arc<String>,
+        type_def: Arc<Type>,
+    },
+    // mutable sibling of a type, `*T`; `**T` when it is shared between
+    // variables with as_shared
+    Mut {
         type_def: Arc<Type>,
+        shared: bool,
     },
+    // placeholder for a type that is not known yet, e.g. the element type of []
+    Unknown,
+    // type of expressions that never produce a value, like `return`
+    Never,
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::TypeDef { name, .. } => write!(f, "{}", name),
+            Type::Generic => write!(f, "Self"),
+            Type::None => write!(f, "None"),
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Uint => write!(f, "uint"),
+            Type::Char => write!(f, "char"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "String"),
+            Type::Array { array_type } => write!(f, "[{}]", array_type),
+            Type::Struct { pairs } if self.is_tuple() => {
+                write!(f, "(")?;
+                for (i, pair) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", pair.resolve_name())?;
+                }
+                if pairs.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Type::Struct { pairs } => {
+                write!(f, "{{")?;
+                for (i, pair) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    match &**pair {
+                        Type::TypeDef { name, type_def } => write!(f, " {}: {}", name, type_def)?,
+                        t => write!(f, " {}", t)?,
+                    }
+                }
+                write!(f, " }}")
+            }
+            Type::Function { param_type, return_type } => write!(f, "|{} -> {}|", param_type, return_type),
+            Type::Optional { members } => match self.optional_of() {
+                Some(t) => write!(f, "?{}", t),
+                None => {
+                    write!(f, "<")?;
+                    for (i, member) in members.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, " | ")?;
+                        }
+                        write!(f, "{}", member)?;
+                    }
+                    write!(f, ">")
+                }
+            },
+            Type::Ghost { name, .. } => write!(f, "{}", name),
+            Type::Mut { type_def, shared: false } => write!(f, "*{}", type_def),
+            Type::Mut { type_def, shared: true } => write!(f, "**{}", type_def),
+            Type::Unknown => write!(f, "_"),
+            Type::Never => write!(f, "!"),
+        }
+    }
+}
+
+// order struct fields are listed in: named fields alphabetically, then
+// tuple positions numerically
+pub fn field_order(name: &str) -> (Option<usize>, &str) {
+    (name.parse().ok(), name)
+}
+
+// Structural equality. A TypeDef is transparent wherever it names a type,
+// but inside a struct it names a field, and field names are part of the
+// struct's type. Ghosts are the exception: they compare by name, and type
+// names are unique since types can't be redeclared.
 impl PartialEq for Type {
     fn eq(&self, other: &Self) -> bool {
-        self.get_sig() == other.get_sig()
+        match (self.resolve(), other.resolve()) {
+            (Type::Array { array_type: a }, Type::Array { array_type: b }) => a == b,
+            (Type::Struct { pairs: a }, Type::Struct { pairs: b }) => Type::fields(a) == Type::fields(b),
+            (
+                Type::Function { param_type: a_param, return_type: a_return },
+                Type::Function { param_type: b_param, return_type: b_return },
+            ) => a_param == b_param && a_return == b_return,
+            (Type::Optional { members: a }, Type::Optional { members: b }) => {
+                a.len() == b.len() && a.iter().all(|t| b.contains(t))
+            }
+            (Type::Ghost { name: a, .. }, Type::Ghost { name: b, .. }) => a == b,
+            (Type::Mut { type_def: a, shared: a_shared }, Type::Mut { type_def: b, shared: b_shared }) => {
+                a_shared == b_shared && a == b
+            }
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+// must agree with PartialEq: equal types hash the same
+impl Hash for Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let t = self.resolve();
+        mem::discriminant(t).hash(state);
+        match t {
+            Type::Array { array_type } => array_type.hash(state),
+            Type::Struct { pairs } => Type::fields(pairs).hash(state),
+            Type::Function { param_type, return_type } => {
+                param_type.hash(state);
+                return_type.hash(state);
+            }
+            // member order doesn't matter, so combine the member hashes with
+            // an order-independent sum
+            Type::Optional { members } => {
+                let sum = members.iter().fold(0u64, |sum, t| {
+                    let mut hasher = DefaultHasher::new();
+                    t.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                sum.hash(state);
+            }
+            Type::Ghost { name, .. } => name.hash(state),
+            Type::Mut { type_def, shared } => {
+                type_def.hash(state);
+                shared.hash(state);
+            }
+            _ => {}
+        }
     }
 }
 impl Type {
     pub fn reduce(t: Arc<Type>) -> Arc<Type> {
-        match &*t {
-            Type::Function { param_type, return_type } => {
+        match t.resolve() {
+            Type::Function { return_type, .. } => {
                 return_type.clone()
             }
             _ => {
@@ -45,66 +185,190 @@ impl Type {
             }
         }
     }
-    pub fn get_sig(&self) -> u64 {
-        self.hash_structure(0)
+
+    // tuples are structs whose keys are their positions
+    pub fn tuple(types: Vec<Arc<Type>>) -> Type {
+        let pairs = types
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| Type::TypeDef { name: i.to_string().into(), type_def: t }.into())
+            .collect();
+        Type::Struct { pairs }
+    }
+
+    // `?T`, the union of T and None
+    pub fn optional(t: Arc<Type>) -> Type {
+        Type::Optional { members: vec![t, Type::None.into()] }
     }
 
+    // T if this is `?T`
+    pub fn optional_of(&self) -> Option<&Arc<Type>> {
+        match self.resolve() {
+            Type::Optional { members } if members.len() == 2 && *members[1] == Type::None => Some(&members[0]),
+            _ => None,
+        }
+    }
 
-    fn hash_structure(&self, state: u64) -> u64 {
-        use Type::*;
+    // first member of a union that is equal to another one, if any
+    pub fn duplicate_member(members: &[Arc<Type>]) -> Option<&Arc<Type>> {
+        members.iter().enumerate().find_map(|(i, t)| members[..i].contains(t).then_some(t))
+    }
 
-        fn combine_hash(state: u64, value: u64) -> u64 {
-            state.wrapping_mul(31).wrapping_add(value)
+    // the type a value of this type reads as, without `*` or `**`
+    pub fn value_type(&self) -> &Type {
+        match self.resolve() {
+            Type::Mut { type_def, .. } => type_def,
+            t => t,
         }
+    }
 
+    pub fn is_shared(&self) -> bool {
+        matches!(self.resolve(), Type::Mut { shared: true, .. })
+    }
+
+    pub fn is_tuple(&self) -> bool {
         match self {
-            TypeDef { name: _, type_def } => {
-                type_def.deref().hash_structure(state)
-            }
-            Generic => {
-                combine_hash(state, 1000000001)
-            }
-            None => {
-                combine_hash(state, 1000000002)
-            }
-            Bool => {
-                combine_hash(state, 1000000003)
-            }
-            Int => {
-                combine_hash(state, 1000000004)
-            }
-            Uint => {
-                combine_hash(state, 1000000005)
-            }
-            Char => {
-                combine_hash(state, 1000000006)
-            }
-            Float => {
-                combine_hash(state, 1000000007)
-            }
-            String => {
-                combine_hash(state, 1000000008)
+            // `()` is None, so an empty struct is never a tuple
+            Type::Struct { pairs } => !pairs.is_empty() && pairs.iter().enumerate().all(|(i, pair)| {
+                matches!(&**pair, Type::TypeDef { name, .. } if **name == i.to_string())
+            }),
+            _ => false,
+        }
+    }
+
+    // looks up a field of a struct type by name
+    pub fn field(&self, field: &str) -> Option<&Arc<Type>> {
+        match self.resolve() {
+            Type::Struct { pairs } => pairs.iter().find_map(|pair| match &**pair {
+                Type::TypeDef { name, type_def } if **name == field => Some(type_def),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    // the type of a struct member, without the field name wrapped around it
+    fn resolve_name(&self) -> &Type {
+        match self {
+            Type::TypeDef { type_def, .. } => type_def,
+            t => t,
+        }
+    }
+
+    // return type of a built-in method taking a parameter of this same type;
+    // the integer methods give 64-bit machine arithmetic on top of the
+    // arbitrary-precision operators
+    pub fn method(&self, name: &str) -> Option<Type> {
+        match self.resolve() {
+            Type::Int | Type::Uint => {
+                let (policy, op) = name.split_once('_')?;
+                if !matches!(op, "add" | "sub" | "mul") {
+                    return None;
+                }
+                match policy {
+                    "wrapping" | "saturating" => Some(self.clone()),
+                    "checked" => Some(Type::optional(self.clone().into())),
+                    _ => None,
+                }
             }
-            Array { array_type } => {
-                let state = combine_hash(state, 1000000009);
-                array_type.hash_structure(state)
+            _ => None,
+        }
+    }
+
+    pub fn resolve(&self) -> &Type {
+        match self {
+            Type::TypeDef { type_def, .. } => type_def.resolve(),
+            t => t,
+        }
+    }
+
+    // whether `a op b` is defined for two operands of this type; this is the
+    // table the interpreter's binary_op goes by as well
+    pub fn supports_operator(&self, op: &Operator) -> bool {
+        match (self.resolve(), op) {
+            (Type::Ghost { type_def, .. }, op) => type_def.supports_operator(op),
+            (Type::Unknown | Type::Never, _) | (_, Operator::Eq | Operator::Neq) => true,
+            (Type::Bool | Type::Int | Type::Uint | Type::Char | Type::Float | Type::String, Operator::Lt | Operator::Gt | Operator::LtEq | Operator::GtEq) => true,
+            (
+                Type::Int | Type::Uint,
+                Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Mod | Operator::BitAnd | Operator::BitOr | Operator::BitXor,
+            ) => true,
+            (Type::Float, Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Mod) => true,
+            (Type::Bool, Operator::And | Operator::Or | Operator::BitAnd | Operator::BitOr | Operator::BitXor) => true,
+            (Type::String | Type::Array { .. }, Operator::Add) => true,
+            _ => false,
+        }
+    }
+
+    pub fn contains_unknown(&self) -> bool {
+        match self {
+            Type::Unknown => true,
+            Type::TypeDef { type_def, .. } => type_def.contains_unknown(),
+            Type::Array { array_type } => array_type.contains_unknown(),
+            Type::Optional { members } => members.iter().any(|t| t.contains_unknown()),
+            Type::Ghost { type_def, .. } | Type::Mut { type_def, .. } => type_def.contains_unknown(),
+            Type::Struct { pairs } => pairs.iter().any(|t| t.contains_unknown()),
+            Type::Function { param_type, return_type } => {
+                param_type.contains_unknown() || return_type.contains_unknown()
             }
-            Struct { pairs } => {
-                let state = combine_hash(state, 1000000010);
-                let mut sorted_pairs = pairs.clone();
-                sorted_pairs.sort();
-                sorted_pairs.into_iter().fold(state, |acc, pair| pair.hash_structure(acc))
+            _ => false,
+        }
+    }
+
+    // merges two types, filling in Unknown from the other side; Never fits
+    // anywhere since control flow doesn't come back from it
+    pub fn unify(&self, other: &Type) -> Option<Type> {
+        match (self.resolve(), other.resolve()) {
+            (Type::Unknown | Type::Never, _) => Some(other.clone()),
+            (_, Type::Unknown | Type::Never) => Some(self.clone()),
+            (Type::Optional { members: a }, Type::Optional { members: b }) => {
+                // same shape: fill in Unknown member by member
+                let pairwise: Option<Vec<Arc<Type>>> = (a.len() == b.len())
+                    .then(|| a.iter().zip(b).map(|(a, b)| a.unify(b).map(Arc::new)).collect())
+                    .flatten();
+                if let Some(members) = pairwise {
+                    Some(Type::Optional { members })
+                } else if b.iter().all(|t| a.contains(t)) {
+                    // a union fits into any union that has all of its members
+                    Some(self.clone())
+                } else if a.iter().all(|t| b.contains(t)) {
+                    Some(other.clone())
+                } else {
+                    None
+                }
             }
-            Function { param_type, return_type } => {
-                let state = combine_hash(state, 1000000011);
-                let state = param_type.hash_structure(state);
-                return_type.hash_structure(state)
+            (Type::Array { array_type: a }, Type::Array { array_type: b }) => {
+                Some(Type::Array { array_type: a.unify(b)?.into() })
             }
-            Optional { type_def } => {
-                let state = combine_hash(state, 1000000012);
-                type_def.hash_structure(state)
+            _ if self == other => Some(self.clone()),
+            _ => None,
+        }
+    }
+
+    // whether a value of type value can be stored where this type is
+    // expected; unlike unify this only goes one way, so a union accepts a
+    // smaller union or a value of one of its members, but not the other
+    // way around
+    pub fn accepts(&self, value: &Type) -> bool {
+        if let Type::Optional { members } = self.resolve() {
+            if members.iter().any(|m| **m == *value) {
+                return true;
             }
         }
+        self.unify(value).is_some_and(|t| t == *self)
     }
-}
 
+    // (field name, field type) of every struct member, ordered by name so
+    // that declaration order doesn't matter
+    fn fields(pairs: &[Arc<Type>]) -> Vec<(Option<&str>, &Type)> {
+        let mut fields: Vec<_> = pairs
+            .iter()
+            .map(|pair| match &**pair {
+                Type::TypeDef { name, type_def } => (Some(name.as_str()), &**type_def),
+                t => (None, t),
+            })
+            .collect();
+        fields.sort_by_key(|(name, _)| *name);
+        fields
+    }
+}